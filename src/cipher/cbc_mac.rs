@@ -0,0 +1,237 @@
+//
+// cbc_mac.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+//! Block cipher MACs over AES-128
+//!
+//! Plain CBC-MAC is only secure for fixed length messages, with a fixed IV. Letting the sender
+//! pick the IV allows the first block to be rewritten, and since the tag is just the final CBC
+//! state, a tag can be used to chain a second message onto the first. CMAC fixes the latter by
+//! mixing a key derived value into the final block.
+
+use super::aes::aes_block_encrypt;
+use super::BLOCK_SIZE;
+use crate::data::Bytes;
+
+/// Raw CBC-MAC of `message`, which is PKCS#7 padded first
+pub fn cbc_mac(message: &Bytes, key: &Bytes, iv: &Bytes) -> Bytes {
+    let mut state = iv.clone();
+    for block in message.pad_pkcs7(BLOCK_SIZE).split(BLOCK_SIZE) {
+        state = aes_block_encrypt(state ^ block, key.clone());
+    }
+    state
+}
+
+pub struct CbcMac {
+    key: Bytes,
+    iv: Bytes,
+}
+
+impl CbcMac {
+    /// CBC-MAC with a zero IV
+    pub fn new(key: Bytes) -> Self {
+        Self {
+            key,
+            iv: Bytes::zero(BLOCK_SIZE),
+        }
+    }
+
+    /// Sets the fixed IV used by `mac` and `verify`
+    pub fn iv(mut self, iv: Bytes) -> Self {
+        self.iv = iv;
+        self
+    }
+
+    pub fn mac(&self, message: &Bytes) -> Bytes {
+        cbc_mac(message, &self.key, &self.iv)
+    }
+
+    pub fn verify(&self, message: &Bytes, tag: &Bytes) -> bool {
+        self.mac(message).ct_eq(tag)
+    }
+
+    /// CBC-MAC with a sender chosen IV, which has to be sent along with the message
+    pub fn mac_with_iv(&self, message: &Bytes, iv: &Bytes) -> Bytes {
+        cbc_mac(message, &self.key, iv)
+    }
+
+    pub fn verify_with_iv(&self, message: &Bytes, iv: &Bytes, tag: &Bytes) -> bool {
+        self.mac_with_iv(message, iv).ct_eq(tag)
+    }
+}
+
+/// AES-CMAC, as defined in RFC 4493
+pub struct Cmac {
+    key: Bytes,
+    k1: Bytes,
+    k2: Bytes,
+}
+
+impl Cmac {
+    pub fn new(key: Bytes) -> Self {
+        let l = aes_block_encrypt(Bytes::zero(BLOCK_SIZE), key.clone());
+        let k1 = double(&l);
+        let k2 = double(&k1);
+        Self { key, k1, k2 }
+    }
+
+    pub fn mac(&self, message: &Bytes) -> Bytes {
+        let complete = !message.is_empty() && message.len().is_multiple_of(BLOCK_SIZE);
+        let mut blocks = if complete {
+            message.split(BLOCK_SIZE)
+        } else {
+            let mut padded = message.clone() + 0x80u8;
+            while !padded.len().is_multiple_of(BLOCK_SIZE) {
+                padded += 0u8;
+            }
+            padded.split(BLOCK_SIZE)
+        };
+        let subkey = if complete { &self.k1 } else { &self.k2 };
+        let last = blocks.pop().unwrap() ^ subkey.clone();
+        let mut state = Bytes::zero(BLOCK_SIZE);
+        for block in blocks {
+            state = aes_block_encrypt(state ^ block, self.key.clone());
+        }
+        aes_block_encrypt(state ^ last, self.key.clone())
+    }
+
+    pub fn verify(&self, message: &Bytes, tag: &Bytes) -> bool {
+        self.mac(message).ct_eq(tag)
+    }
+}
+
+/// Multiplies a block by x in GF(2^128), for deriving the CMAC subkeys
+fn double(block: &Bytes) -> Bytes {
+    let mut ret = Bytes::zero(BLOCK_SIZE);
+    for i in 0..BLOCK_SIZE {
+        ret[i] = block[i] << 1;
+        if i + 1 < BLOCK_SIZE {
+            ret[i] |= block[i + 1] >> 7;
+        }
+    }
+    if block[0] & 0x80 != 0 {
+        ret[BLOCK_SIZE - 1] ^= 0x87;
+    }
+    ret
+}
+
+/// Finds the IV that gives `forged` the same variable IV CBC-MAC that `message` has under `iv`
+///
+/// `forged` can only differ from `message` within the first block, since the IV only feeds into
+/// the first block cipher call. Panics if it differs anywhere else.
+pub fn forge_iv(message: &Bytes, iv: &Bytes, forged: &Bytes) -> Bytes {
+    assert_eq!(
+        message.len(),
+        forged.len(),
+        "The forged message has to be the same length"
+    );
+    assert!(
+        message.len() <= BLOCK_SIZE || message[BLOCK_SIZE..] == forged[BLOCK_SIZE..],
+        "Only the first block can be changed"
+    );
+    let first = |m: &Bytes| m.pad_pkcs7(BLOCK_SIZE).truncate(BLOCK_SIZE);
+    iv ^ &(first(message) ^ first(forged))
+}
+
+/// Chains `second` onto `first`, when both have known tags under the same key and fixed IV
+///
+/// The result is `first` with its padding, followed by `second` with its first block altered to
+/// cancel out `first_tag`. Its CBC-MAC is the same as the tag of `second`, so the returned message
+/// is accepted with that tag. The altered block will be garbage, so `second` should be chosen so
+/// that its first block doesn't matter.
+pub fn forge_extension(first: &Bytes, first_tag: &Bytes, second: &Bytes, iv: &Bytes) -> Bytes {
+    let mut spliced = second.clone();
+    for i in 0..BLOCK_SIZE.min(second.len()) {
+        spliced[i] ^= first_tag[i] ^ iv[i];
+    }
+    first.pad_pkcs7(BLOCK_SIZE) + spliced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rfc_4493_message() -> Bytes {
+        Bytes::read_hex(concat!(
+            "6bc1bee22e409f96e93d7e117393172a",
+            "ae2d8a571e03ac9c9eb76fac45af8e51",
+            "30c81c46a35ce411e5fbc1191a0a52ef",
+            "f69f2445df4f9b17ad2b417be66c3710",
+        ))
+    }
+
+    #[test]
+    fn cmac() {
+        let cmac = Cmac::new(Bytes::read_hex("2b7e151628aed2a6abf7158809cf4f3c"));
+        assert_eq!(cmac.k1, "fbeed618357133667c85e08f7236a8de");
+        assert_eq!(cmac.k2, "f7ddac306ae266ccf90bc11ee46d513b");
+        let message = rfc_4493_message();
+        for (len, tag) in [
+            (0, "bb1d6929e95937287fa37d129b756746"),
+            (16, "070a16b46b4d4144f79bdd9dd04a287c"),
+            (40, "dfa66747de9ae63030ca32611497c827"),
+            (64, "51f0bebf7e3b9d92fc49741779363cfe"),
+        ] {
+            let message = message.truncate(len);
+            assert_eq!(cmac.mac(&message), tag);
+            assert!(cmac.verify(&message, &Bytes::read_hex(tag)));
+        }
+    }
+
+    #[test]
+    fn cbc_mac_verify() {
+        let mac = CbcMac::new(Bytes::rand(16)).iv(Bytes::rand(16));
+        let message = Bytes::read_utf8("from=alice&to=bob&amount=10");
+        let tag = mac.mac(&message);
+        assert!(mac.verify(&message, &tag));
+        assert!(!mac.verify(&Bytes::read_utf8("from=alice&to=bob&amount=99"), &tag));
+    }
+
+    #[test]
+    fn iv_forgery() {
+        let mac = CbcMac::new(Bytes::rand(16));
+        let message = Bytes::read_utf8("to=bob&from=alice&amount=1000000");
+        let iv = Bytes::rand(16);
+        let tag = mac.mac_with_iv(&message, &iv);
+
+        let forged = Bytes::read_utf8("to=eve&from=alice&amount=1000000");
+        let forged_iv = forge_iv(&message, &iv, &forged);
+        assert!(mac.verify_with_iv(&forged, &forged_iv, &tag));
+
+        let short = Bytes::read_utf8("to=bob");
+        let tag = mac.mac_with_iv(&short, &iv);
+        let forged = Bytes::read_utf8("to=eve");
+        assert!(mac.verify_with_iv(&forged, &forge_iv(&short, &iv, &forged), &tag));
+    }
+
+    #[test]
+    fn extension_forgery() {
+        for mac in [
+            CbcMac::new(Bytes::rand(16)),
+            CbcMac::new(Bytes::rand(16)).iv(Bytes::rand(16)),
+        ] {
+            let iv = mac.iv.clone();
+            let first = Bytes::read_utf8("from=alice&tx_list=bob:10;carol:20");
+            let first_tag = mac.mac(&first);
+            let second = Bytes::read_utf8("AAAAAAAAAAAAAAAA;eve:1000000");
+            let second_tag = mac.mac(&second);
+
+            let forged = forge_extension(&first, &first_tag, &second, &iv);
+            assert!(mac.verify(&forged, &second_tag));
+            assert!(forged
+                .to_utf8()
+                .starts_with("from=alice&tx_list=bob:10;carol:20"));
+            assert!(forged.to_utf8().ends_with(";eve:1000000"));
+        }
+    }
+
+    #[test]
+    fn cmac_resists_extension() {
+        let cmac = Cmac::new(Bytes::rand(16));
+        let first = Bytes::read_utf8("from=alice&tx_list=bob:10;carol:20");
+        let second = Bytes::read_utf8("AAAAAAAAAAAAAAAA;eve:1000000");
+        let forged = forge_extension(&first, &cmac.mac(&first), &second, &Bytes::zero(16));
+        assert!(!cmac.verify(&forged, &cmac.mac(&second)));
+    }
+}
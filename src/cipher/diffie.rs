@@ -6,20 +6,113 @@
 #![allow(non_snake_case)]
 //! Diffie-Hellman algo:
 //! ```
-//! let (a, A) = diffie_helman_weak_a();
-//! let B = ();// send A and get B (the other side's A)
-//! let shared_key = diffie_helman_weak_key(a, B);
+//! use cryptopals::cipher::diffie::MODP_2048;
+//!
+//! let (a, A) = MODP_2048.keypair();
+//! let (b, B) = MODP_2048.keypair(); // send A and get B (the other side's A)
+//! let shared_key = MODP_2048.shared_secret(&a, &B).unwrap();
+//! assert_eq!(shared_key, MODP_2048.shared_secret(&b, &A).unwrap());
 //! ```
 use lazy_static::lazy_static;
 
 use num_bigint::BigUint;
 use num_bigint::{RandBigInt, ToBigUint};
 
+use crate::data::Bytes;
+use crate::hash::{sha2::Sha256, Digest};
+
 lazy_static! {
     pub static ref WEAK_P: BigUint = 37usize.to_biguint().unwrap();
     pub static ref WEAK_G: BigUint = 5usize.to_biguint().unwrap();
     pub static ref NIST_P: BigUint = BigUint::parse_bytes(b"ffffffffffffffffc90fdaa22168c234c4c6628b80dc1cd129024e088a67cc74020bbea63b139b22514a08798e3404ddef9519b3cd3a431b302b0a6df25f14374fe1356d6d51c245e485b576625e7ec6f44c42e9a637ed6b0bff5cb6f406b7edee386bfb5a899fa5ae9f24117c4b1fe649286651ece45b3dc2007cb8a163bf0598da48361c55d39a69163fa8fd24cf5f83655d23dca3ad961c62f356208552bb9ed529077096966d670c354e4abc9804f1746c08ca237327ffffffffffffffff", 16).unwrap();
     pub static ref NIST_G: BigUint = 2usize.to_biguint().unwrap();
+    /// The 1536-bit group from RFC 3526
+    pub static ref MODP_1536: DhGroup = DhGroup::safe_prime(
+        "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E\
+         3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF\
+         5CB6F406B7EDEE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF0598DA48361C55D3\
+         9A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB9ED529077096966D670C354E4ABC9804F1746C08\
+         CA237327FFFFFFFFFFFFFFFF",
+        2
+    );
+    /// The 2048-bit group from RFC 3526
+    pub static ref MODP_2048: DhGroup = DhGroup::safe_prime(
+        "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E\
+         3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF\
+         5CB6F406B7EDEE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF0598DA48361C55D3\
+         9A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB9ED529077096966D670C354E4ABC9804F1746C08\
+         CA18217C32905E462E36CE3BE39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF69558171839\
+         95497CEA956AE515D2261898FA051015728E5A8AACAA68FFFFFFFFFFFFFFFF",
+        2
+    );
+    /// The 3072-bit group from RFC 3526
+    pub static ref MODP_3072: DhGroup = DhGroup::safe_prime(
+        "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E\
+         3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF\
+         5CB6F406B7EDEE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF0598DA48361C55D3\
+         9A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB9ED529077096966D670C354E4ABC9804F1746C08\
+         CA18217C32905E462E36CE3BE39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF69558171839\
+         95497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33A85521ABDF1CBA64ECFB850458DB\
+         EF0A8AEA71575D060C7DB3970F85A6E1E4C7ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA\
+         06D98A0864D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E208E24FA074E5AB31\
+         43DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF",
+        2
+    );
+    /// The 4096-bit group from RFC 3526
+    pub static ref MODP_4096: DhGroup = DhGroup::safe_prime(
+        "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E\
+         3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF\
+         5CB6F406B7EDEE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF0598DA48361C55D3\
+         9A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB9ED529077096966D670C354E4ABC9804F1746C08\
+         CA18217C32905E462E36CE3BE39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF69558171839\
+         95497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33A85521ABDF1CBA64ECFB850458DB\
+         EF0A8AEA71575D060C7DB3970F85A6E1E4C7ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA\
+         06D98A0864D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E208E24FA074E5AB31\
+         43DB5BFCE0FD108E4B82D120A92108011A723C12A787E6D788719A10BDBA5B2699C327186AF4E23C1A946834B6\
+         150BDA2583E9CA2AD44CE8DBBBC2DB04DE8EF92E8EFC141FBECAA6287C59474E6BC05D99B2964FA090C3A2233B\
+         A186515BE7ED1F612970CEE2D7AFB81BDD762170481CD0069127D5B05AA993B4EA988D8FDDC186FFB7DC90A6C0\
+         8F4DF435C934063199FFFFFFFFFFFFFFFF",
+        2
+    );
+    /// The 2048-bit group from RFC 7919
+    pub static ref FFDHE_2048: DhGroup = DhGroup::safe_prime(
+        "FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695A9E13641146433FBCC939DCE24\
+         9B3EF97D2FE363630C75D8F681B202AEC4617AD3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557\
+         135E7F57C935984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797ABC0AB182B324FB\
+         61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1\
+         CDF7E2ECC03404CD28342F619172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005C5\
+         8EF1837D1683B2C6F34A26C1B2EFFA886B423861285C97FFFFFFFFFFFFFFFF",
+        2
+    );
+    /// The 3072-bit group from RFC 7919
+    pub static ref FFDHE_3072: DhGroup = DhGroup::safe_prime(
+        "FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695A9E13641146433FBCC939DCE24\
+         9B3EF97D2FE363630C75D8F681B202AEC4617AD3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557\
+         135E7F57C935984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797ABC0AB182B324FB\
+         61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1\
+         CDF7E2ECC03404CD28342F619172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005C5\
+         8EF1837D1683B2C6F34A26C1B2EFFA886B4238611FCFDCDE355B3B6519035BBC34F4DEF99C023861B46FC9D6E6\
+         C9077AD91D2691F7F7EE598CB0FAC186D91CAEFE130985139270B4130C93BC437944F4FD4452E2D74DD364F2E2\
+         1E71F54BFF5CAE82AB9C9DF69EE86D2BC522363A0DABC521979B0DEADA1DBF9A42D5C4484E0ABCD06BFA53DDEF\
+         3C1B20EE3FD59D7C25E41D2B66C62E37FFFFFFFFFFFFFFFF",
+        2
+    );
+    /// The 4096-bit group from RFC 7919
+    pub static ref FFDHE_4096: DhGroup = DhGroup::safe_prime(
+        "FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695A9E13641146433FBCC939DCE24\
+         9B3EF97D2FE363630C75D8F681B202AEC4617AD3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557\
+         135E7F57C935984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797ABC0AB182B324FB\
+         61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1\
+         CDF7E2ECC03404CD28342F619172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005C5\
+         8EF1837D1683B2C6F34A26C1B2EFFA886B4238611FCFDCDE355B3B6519035BBC34F4DEF99C023861B46FC9D6E6\
+         C9077AD91D2691F7F7EE598CB0FAC186D91CAEFE130985139270B4130C93BC437944F4FD4452E2D74DD364F2E2\
+         1E71F54BFF5CAE82AB9C9DF69EE86D2BC522363A0DABC521979B0DEADA1DBF9A42D5C4484E0ABCD06BFA53DDEF\
+         3C1B20EE3FD59D7C25E41D2B669E1EF16E6F52C3164DF4FB7930E9E4E58857B6AC7D5F42D69F6D187763CF1D55\
+         03400487F55BA57E31CC7A7135C886EFB4318AED6A1E012D9E6832A907600A918130C46DC778F971AD00380929\
+         99A333CB8B7A1A1DB93D7140003C2A4ECEA9F98D0ACC0A8291CDCEC97DCF8EC9B55A7F88A46B4DB5A851F44182\
+         E1C68A007E5E655F6AFFFFFFFFFFFFFFFF",
+        2
+    );
 }
 
 pub fn p_bytes() -> BigUint {
@@ -44,15 +137,174 @@ pub fn diffie_hellman_weak_key(a: BigUint, B: BigUint) -> BigUint {
 ///
 /// returns (a, A)
 pub fn diffie_hellman_a() -> (BigUint, BigUint) {
-    let mut rng = rand::thread_rng();
-    // private key < P
-    let a = rng.gen_biguint(10) % &*NIST_P;
-    // G ^ a = A
-    let A = NIST_G.modpow(&a, &*NIST_P);
-    (a, A)
+    // NIST_P and NIST_G are the RFC 3526 1536-bit group
+    MODP_1536.keypair()
 }
 
 /// Generates the shared secret from a and B
 pub fn diffie_hellman_key(a: BigUint, B: BigUint) -> BigUint {
     B.modpow(&a, &*NIST_P)
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DhError {
+    /// The public value wasn't in 1 < y < p - 1
+    OutOfRange,
+    /// y^q != 1, so the public value is outside the subgroup generated by g
+    NotInSubgroup,
+}
+
+/// A prime modulus `p` and generator `g`, with the order `q` of g if it's known
+#[derive(Debug, Clone, PartialEq)]
+pub struct DhGroup {
+    pub p: BigUint,
+    pub g: BigUint,
+    pub q: Option<BigUint>,
+}
+
+impl DhGroup {
+    /// A group where the order of `g` isn't known, so public values can only be range checked
+    pub fn new(p: BigUint, g: BigUint) -> Self {
+        Self { p, g, q: None }
+    }
+
+    /// Sets the order of `g`, for subgroup checks and smaller private keys
+    pub fn order(mut self, q: BigUint) -> Self {
+        self.q = Some(q);
+        self
+    }
+
+    /// A group mod the safe prime `p` = 2q + 1, where `g` is a square so it has order q
+    fn safe_prime(p: &str, g: u32) -> Self {
+        let p = BigUint::parse_bytes(p.as_bytes(), 16).unwrap();
+        let q = (&p - 1u32) >> 1;
+        Self::new(p, BigUint::from(g)).order(q)
+    }
+
+    /// The length of p in bytes
+    pub fn byte_len(&self) -> usize {
+        self.p.bits().div_ceil(8) as usize
+    }
+
+    /// A uniformly random exponent in [2, q), or [2, p - 1) if q isn't known
+    pub fn private_key(&self) -> BigUint {
+        let upper = match &self.q {
+            Some(q) => q.clone(),
+            None => &self.p - 1u32,
+        };
+        rand::thread_rng().gen_biguint_range(&BigUint::from(2u32), &upper)
+    }
+
+    /// Returns (x, g^x)
+    pub fn keypair(&self) -> (BigUint, BigUint) {
+        let x = self.private_key();
+        let y = self.g.modpow(&x, &self.p);
+        (x, y)
+    }
+
+    /// Checks the other side's public value
+    ///
+    /// 0, 1 and p - 1 are rejected outright, since they force the shared secret to one of
+    /// those values. The subgroup check also needs q, and stops a malicious y leaking the
+    /// private key mod the small factors of p - 1.
+    pub fn validate(&self, y: &BigUint) -> Result<(), DhError> {
+        if *y <= BigUint::from(1u32) || *y >= &self.p - 1u32 {
+            return Err(DhError::OutOfRange);
+        }
+        match &self.q {
+            Some(q) if y.modpow(q, &self.p) != BigUint::from(1u32) => Err(DhError::NotInSubgroup),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validates `their_public`, and raises it to `private`
+    pub fn shared_secret(
+        &self,
+        private: &BigUint,
+        their_public: &BigUint,
+    ) -> Result<BigUint, DhError> {
+        self.validate(their_public)?;
+        Ok(their_public.modpow(private, &self.p))
+    }
+
+    /// AES-128 key from the shared secret, as the first 16 bytes of SHA-256 of the secret
+    /// padded to the length of p
+    pub fn session_key(&self, secret: &BigUint) -> Bytes {
+        let bytes = secret.to_bytes_be();
+        let padded =
+            Bytes::zero(self.byte_len().saturating_sub(bytes.len())) + Bytes::from_vec(bytes);
+        Sha256::digest(&padded).truncate(16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_groups() {
+        assert_eq!(MODP_1536.p, *NIST_P);
+        for (group, bits) in [
+            (&*MODP_1536, 1536),
+            (&*MODP_2048, 2048),
+            (&*MODP_3072, 3072),
+            (&*MODP_4096, 4096),
+            (&*FFDHE_2048, 2048),
+            (&*FFDHE_3072, 3072),
+            (&*FFDHE_4096, 4096),
+        ] {
+            assert_eq!(group.p.bits(), bits);
+            assert_eq!(group.byte_len() * 8, bits as usize);
+            let q = group.q.as_ref().unwrap();
+            assert_eq!(group.g.modpow(q, &group.p), BigUint::from(1u32));
+        }
+        assert!(FFDHE_2048
+            .p
+            .to_str_radix(16)
+            .starts_with("ffffffffffffffffadf85458a2bb4a9a"));
+        assert!(MODP_2048
+            .p
+            .to_str_radix(16)
+            .ends_with("15728e5a8aacaa68ffffffffffffffff"));
+    }
+
+    #[test]
+    fn agreement() {
+        let (a, a_pub) = MODP_2048.keypair();
+        let (b, b_pub) = MODP_2048.keypair();
+        assert!(a.bits() > 2000);
+        let s = MODP_2048.shared_secret(&a, &b_pub).unwrap();
+        assert_eq!(s, MODP_2048.shared_secret(&b, &a_pub).unwrap());
+        assert_eq!(MODP_2048.session_key(&s).len(), 16);
+        assert_ne!(
+            MODP_2048.session_key(&s),
+            MODP_2048.session_key(&(s + 1u32))
+        );
+    }
+
+    #[test]
+    fn validation() {
+        let group = &*FFDHE_2048;
+        let p = &group.p;
+        for y in [
+            BigUint::from(0u32),
+            BigUint::from(1u32),
+            p - 1u32,
+            p.clone(),
+            p + 2u32,
+        ] {
+            assert_eq!(group.validate(&y), Err(DhError::OutOfRange));
+        }
+        // g = 2 is a square mod these primes, and so is 4, but a non-square has order 2q
+        assert_eq!(group.validate(&BigUint::from(4u32)), Ok(()));
+        let non_square = p - 4u32;
+        assert_eq!(group.validate(&non_square), Err(DhError::NotInSubgroup));
+        let (x, _) = group.keypair();
+        assert!(group.shared_secret(&x, &non_square).is_err());
+
+        // Without q, only the range can be checked
+        let custom = DhGroup::new(p.clone(), group.g.clone());
+        assert_eq!(custom.validate(&non_square), Ok(()));
+        assert!(custom.private_key() < p - 1u32);
+    }
+}
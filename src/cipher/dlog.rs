@@ -0,0 +1,528 @@
+//
+// dlog.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Discrete logarithms
+//!
+//! Pollard's kangaroo finds x in a known interval [a, b] with y = g^x in about sqrt(b - a) steps,
+//! which is what's needed to finish off a private key when only part of it is known, as in
+//! challenge 58.
+//!
+//! All of them are generic over `Group`, so they work for `ModN` as well as curve points. Baby-step
+//! giant-step and Pollard's rho both take about sqrt(n) steps in a group of order n, BSGS with a
+//! table of that size and rho in constant memory. Pohlig-Hellman splits a smooth n into its prime
+//! powers, and solves each with BSGS.
+//!
+//! `Group` has no way to pass the group itself around, since `identity` and `op` only see
+//! elements, so the curve has to be part of the element's type. That rules out
+//! `eliptic_curve::Point`, which only gets added through a `WeienerstrassCurve` holding a and b
+//! at runtime, and is why curves here are `modulus::CurvePoint`, with the curve as a type
+//! parameter.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use num_bigint::{BigInt, BigUint, RandBigInt};
+use num_traits::ToPrimitive;
+use rayon::prelude::*;
+
+use super::modulus::{CurvePoint, ElipticCurve, ModN, PrimeMod};
+use super::subgroup::small_factors;
+use super::util::{crt, extended_euclid};
+
+/// A finite group, written multiplicatively
+pub trait Group: Clone + Eq + Hash {
+    fn identity() -> Self;
+    fn op(&self, rhs: &Self) -> Self;
+    fn inverse(&self) -> Self;
+
+    /// `self` combined with itself `n` times, by square and multiply
+    fn pow(&self, n: &BigUint) -> Self {
+        let mut result = Self::identity();
+        for i in (0..n.bits()).rev() {
+            result = result.op(&result);
+            if n.bit(i) {
+                result = result.op(self);
+            }
+        }
+        result
+    }
+}
+
+/// The multiplicative group mod M
+impl<M: PrimeMod> Group for ModN<M> {
+    fn identity() -> Self {
+        ModN::new(1u32)
+    }
+
+    fn op(&self, rhs: &Self) -> Self {
+        self * rhs
+    }
+
+    fn inverse(&self) -> Self {
+        self.clone().mul_inverse()
+    }
+}
+
+/// Points on a curve under addition, so `pow` is scalar multiplication
+impl<M: PrimeMod, E: ElipticCurve<M>> Group for CurvePoint<M, E> {
+    fn identity() -> Self {
+        CurvePoint::new_zero()
+    }
+
+    fn op(&self, rhs: &Self) -> Self {
+        self + rhs
+    }
+
+    fn inverse(&self) -> Self {
+        match self.coords() {
+            Some((x, y)) => CurvePoint::Pt(x.clone(), ModN::new(0u32) - y, Default::default()),
+            None => CurvePoint::new_zero(),
+        }
+    }
+}
+
+/// Baby-step giant-step: finds x < n with g^x = h
+///
+/// With m = ceil(sqrt(n)), stores g^j for j < m, then steps h * g^-mi until it hits one, and x is
+/// mi + j. `n` only has to bound x, so it doesn't need to be the order of g.
+pub fn bsgs<G: Group>(g: &G, h: &G, n: &BigUint) -> Option<BigUint> {
+    let mut m = n.sqrt();
+    if &m * &m < *n {
+        m += 1u32;
+    }
+    let m_usize = m.to_usize()?;
+    let mut table = HashMap::with_capacity(m_usize);
+    let mut baby = G::identity();
+    for j in 0..m_usize {
+        // Keep the smallest j, in case g has order less than m
+        table.entry(baby.clone()).or_insert(j);
+        baby = baby.op(g);
+    }
+    let giant = g.pow(&m).inverse();
+    let mut y = h.clone();
+    for i in 0..m_usize {
+        if let Some(&j) = table.get(&y) {
+            let x = &m * i + j;
+            return if x < *n { Some(x) } else { None };
+        }
+        y = y.op(&giant);
+    }
+    None
+}
+
+/// Factors `n` into (prime, exponent) pairs, if all its prime factors are below `bound`
+pub fn factor_smooth(n: &BigUint, bound: u32) -> Option<Vec<(BigUint, u32)>> {
+    let mut rest = n.clone();
+    let mut factors = vec![];
+    for p in small_factors(n, bound) {
+        let mut e = 0;
+        while (&rest % p).bits() == 0 {
+            rest /= p;
+            e += 1;
+        }
+        factors.push((BigUint::from(p), e));
+    }
+    if rest == BigUint::from(1u32) {
+        Some(factors)
+    } else {
+        None
+    }
+}
+
+/// Pohlig-Hellman: finds x with g^x = h, where g has order n = the product of `factors`
+///
+/// For each p^e, x mod p^e is found one base p digit at a time, each by BSGS in the subgroup of
+/// order p, and the residues are recombined with the CRT.
+pub fn pohlig_hellman<G: Group>(g: &G, h: &G, factors: &[(BigUint, u32)]) -> Option<BigUint> {
+    let n = factors
+        .iter()
+        .fold(BigUint::from(1u32), |n, (p, e)| n * p.pow(*e));
+    let (mut residues, mut moduli) = (vec![], vec![]);
+    for (p, e) in factors {
+        // gamma has order p
+        let gamma = g.pow(&(&n / p));
+        let g_inv = g.inverse();
+        let mut x = BigUint::from(0u32);
+        let mut pk = BigUint::from(1u32);
+        for k in 0..*e {
+            // Removing the digits found so far leaves the next one, times p^k
+            let hk = g_inv.pow(&x).op(h).pow(&(&n / (&pk * p)));
+            let d = bsgs(&gamma, &hk, p)?;
+            x += d * &pk;
+            if k + 1 < *e {
+                pk *= p;
+            }
+        }
+        residues.push(BigInt::from(x));
+        moduli.push(BigInt::from(pk * p));
+    }
+    if moduli.is_empty() {
+        return Some(BigUint::from(0u32));
+    }
+    let (x, m) = crt(&residues, &moduli);
+    let x = ((x % &m) + &m) % &m;
+    let x = x.to_biguint().unwrap();
+    if g.pow(&x) == *h {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+/// A pseudorandom index below `n`, from a hash of `y`, for picking a step in a random walk
+fn hash_index<G: Hash>(y: &G, n: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    y.hash(&mut hasher);
+    hasher.finish() % n
+}
+
+/// Pollard's rho for logarithms: finds x < n with g^x = h, where g has order n
+///
+/// Walks y = g^a h^b pseudorandomly, picking one of three steps from a hash of y, until Floyd's
+/// cycle finding turns up g^a1 h^b1 = g^a2 h^b2, so (b1 - b2) x = a2 - a1 mod n. n should be
+/// prime, or at least have no large factors shared with b1 - b2; otherwise the walk restarts, up
+/// to `tries` times.
+pub fn pollard_rho<G: Group>(g: &G, h: &G, n: &BigUint, tries: usize) -> Option<BigUint> {
+    let step = |(y, a, b): (G, BigUint, BigUint)| match hash_index(&y, 3) {
+        0 => (y.op(g), (a + 1u32) % n, b),
+        1 => (y.op(&y), (&a * 2u32) % n, (&b * 2u32) % n),
+        _ => (y.op(h), a, (b + 1u32) % n),
+    };
+    let n_int = BigInt::from(n.clone());
+    for _ in 0..tries {
+        let a = rand::thread_rng().gen_biguint_below(n);
+        let b = rand::thread_rng().gen_biguint_below(n);
+        let start = (g.pow(&a).op(&h.pow(&b)), a, b);
+        let mut tortoise = step(start.clone());
+        let mut hare = step(step(start));
+        while tortoise.0 != hare.0 {
+            tortoise = step(tortoise);
+            hare = step(step(hare));
+        }
+        let (_, a1, b1) = tortoise;
+        let (_, a2, b2) = hare;
+        let r = (BigInt::from(b1) - BigInt::from(b2) + &n_int) % &n_int;
+        let s = (BigInt::from(a2) - BigInt::from(a1) + &n_int) % &n_int;
+        let (u, _, d) = extended_euclid(r, n_int.clone());
+        // r x = s has d solutions mod n if d divides s, and none otherwise
+        if d == BigInt::from(0u32)
+            || (&s % &d) != BigInt::from(0u32)
+            || d > BigInt::from(1u32 << 16)
+        {
+            continue;
+        }
+        let step_size = &n_int / &d;
+        let x0 = ((u * (&s / &d)) % &step_size + &step_size) % &step_size;
+        let mut x = x0;
+        while x < n_int {
+            let candidate = x.to_biguint().unwrap();
+            if g.pow(&candidate) == *h {
+                return Some(candidate);
+            }
+            x += &step_size;
+        }
+    }
+    None
+}
+
+/// Pollard's kangaroo (lambda) algorithm
+///
+/// A tame kangaroo starts at g^b and makes a fixed number of pseudorandom jumps, each of g^(2^j)
+/// with j picked from a hash of where it is, leaving a trap where it stops. Wild kangaroos start
+/// at y, and follow the same jumps, so if one ever lands where the tame one did it follows it
+/// into the trap, and the distances travelled give x.
+#[derive(Debug, Clone)]
+pub struct Kangaroo<G: Group> {
+    g: G,
+    k: Option<u32>,
+    wild: usize,
+    tries: usize,
+}
+
+/// k for an interval of width `width`, as the smallest that gives a big enough mean jump
+fn k_for(width: &BigUint) -> u32 {
+    let target = width.sqrt() / 2u32;
+    (1..)
+        .find(|&k: &u32| (BigUint::from(1u32) << k) > &target * k)
+        .unwrap()
+}
+
+impl<G: Group + Send + Sync> Kangaroo<G> {
+    pub fn new(g: G) -> Self {
+        Self {
+            g,
+            k: None,
+            wild: 1,
+            tries: 1,
+        }
+    }
+
+    /// Sets the size of the jump table, which is otherwise picked from the size of the interval
+    ///
+    /// The mean jump of (2^k - 1) / k should be about sqrt(b - a) / 2.
+    pub fn jumps(mut self, k: u32) -> Self {
+        self.k = Some(k);
+        self
+    }
+
+    /// Runs `wild` kangaroos in parallel, each starting at a different random offset from y
+    pub fn wild(mut self, wild: usize) -> Self {
+        self.wild = wild.max(1);
+        self
+    }
+
+    /// Starts over with new wild kangaroos this many times before giving up
+    ///
+    /// Each attempt finds x with high probability, but not certainty
+    pub fn tries(mut self, tries: usize) -> Self {
+        self.tries = tries.max(1);
+        self
+    }
+
+    /// Finds x in [a, b] with g^x = y
+    pub fn solve(&self, y: &G, a: &BigUint, b: &BigUint) -> Option<BigUint> {
+        if a > b {
+            return None;
+        }
+        let width = b - a;
+        let k = self.k.unwrap_or_else(|| k_for(&width));
+        // g^(2^i), so each jump is one group operation
+        let table: Vec<G> = (0..k)
+            .scan(self.g.clone(), |g, _| {
+                let jump = g.clone();
+                *g = g.op(g);
+                Some(jump)
+            })
+            .collect();
+        let jump = |y: &G| hash_index(y, k as u64) as usize;
+        let mean = ((BigUint::from(1u32) << k) - 1u32) / k;
+
+        let mut tame_distance = BigUint::from(0u32);
+        let mut trap = self.g.pow(b);
+        let steps = (&mean * 4u32).max(BigUint::from(1u32));
+        let mut i = BigUint::from(0u32);
+        while i < steps {
+            let j = jump(&trap);
+            tame_distance += BigUint::from(1u32) << j;
+            trap = trap.op(&table[j]);
+            i += 1u32;
+        }
+
+        // Wild kangaroos that pass distance `limit` have overtaken the trap without falling in
+        let limit = &width + &tame_distance;
+        let found = AtomicBool::new(false);
+        let run_wild = |offset: BigUint| {
+            let mut pos = y.op(&self.g.pow(&offset));
+            let mut distance = offset;
+            while distance <= limit {
+                if pos == trap {
+                    // y can also be caught when x is a little above b, which is out of bounds
+                    let x = b + &tame_distance - &distance;
+                    return if &x <= b { Some(x) } else { None };
+                }
+                if found.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let j = jump(&pos);
+                distance += BigUint::from(1u32) << j;
+                pos = pos.op(&table[j]);
+            }
+            None
+        };
+        for attempt in 0..self.tries {
+            // The first kangaroo starts exactly at y, and the rest are spread over a mean jump
+            let offsets: Vec<BigUint> = (0..self.wild)
+                .map(|w| match (attempt, w) {
+                    (0, 0) => BigUint::from(0u32),
+                    _ => rand::thread_rng().gen_biguint_below(&(&mean + 1u32)),
+                })
+                .collect();
+            let x = offsets.into_par_iter().find_map_any(|offset| {
+                let x = run_wild(offset)?;
+                found.store(true, Ordering::Relaxed);
+                Some(x)
+            });
+            if x.is_some() {
+                return x;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::modulus::{TestCurve, N_CH58, N_CURVE, N_RHO, N_SMOOTH};
+
+    type Point = CurvePoint<N_CURVE, TestCurve<N_CURVE>>;
+
+    fn ch58() -> ModN<N_CH58> {
+        ModN::new(
+            BigUint::parse_bytes(
+                b"6229523353339612969781592660847410858898813587384599399782901799360636355667402585\
+                  55167783009058567397963466103140082647486611657350811560630587013183357",
+                10,
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn challenge_58() {
+        let y = ModN::<N_CH58>::new(
+            BigUint::parse_bytes(
+                b"7760073848032689505395005705677365876654629189298052775754597607446617558600394076\
+                  764814236081991643094239886772481052254010323780165093955236429914607119",
+                10,
+            )
+            .unwrap(),
+        );
+        let x = Kangaroo::new(ch58()).tries(4).solve(
+            &y,
+            &BigUint::from(0u32),
+            &BigUint::from(1u32 << 20),
+        );
+        assert_eq!(x, Some(BigUint::from(705485u32)));
+    }
+
+    #[test]
+    fn interval() {
+        let g = ch58();
+        let kangaroo = Kangaroo::new(g.clone()).wild(4).tries(4);
+        let a = BigUint::from(1u64 << 40);
+        let b = &a + (1u32 << 16);
+        for _ in 0..3 {
+            let x = rand::thread_rng().gen_biguint_range(&a, &(&b + 1u32));
+            let y = Group::pow(&g, &x);
+            assert_eq!(kangaroo.solve(&y, &a, &b), Some(x));
+        }
+        // Out of range, so the kangaroos give up
+        let y = Group::pow(&g, &(&b + 100_000u32));
+        assert_eq!(kangaroo.solve(&y, &a, &b), None);
+        assert_eq!(kangaroo.jumps(12).solve(&g, &a, &b), None);
+    }
+
+    #[test]
+    fn kangaroo_curve() {
+        let g = Point::new(BigUint::from(4u32), BigUint::from(1777152513u32))
+            .ok()
+            .unwrap();
+        let kangaroo = Kangaroo::new(g.clone()).wild(4).tries(4);
+        let a = BigUint::from(1u32 << 20);
+        let b = &a + (1u32 << 14);
+        for _ in 0..3 {
+            let x = rand::thread_rng().gen_biguint_range(&a, &(&b + 1u32));
+            let h = Group::pow(&g, &x);
+            assert_eq!(kangaroo.solve(&h, &a, &b), Some(x));
+        }
+    }
+
+    #[test]
+    fn jump_size() {
+        assert_eq!(k_for(&BigUint::from(1u32 << 20)), 13);
+        assert_eq!(k_for(&BigUint::from(0u32)), 1);
+    }
+
+    #[test]
+    fn baby_giant() {
+        let g = ModN::<N_SMOOTH>::new(3u32);
+        for x in [0u32, 1, 999, 123_456] {
+            let h = Group::pow(&g, &BigUint::from(x));
+            assert_eq!(
+                bsgs(&g, &h, &BigUint::from(1u32 << 20)),
+                Some(BigUint::from(x))
+            );
+        }
+        let h = Group::pow(&g, &BigUint::from(5000u32));
+        assert_eq!(bsgs(&g, &h, &BigUint::from(4000u32)), None);
+    }
+
+    #[test]
+    fn smooth_factors() {
+        let n = N_SMOOTH.clone() - 1u32;
+        let factors: Vec<(u32, u32)> = factor_smooth(&n, 2000)
+            .unwrap()
+            .into_iter()
+            .map(|(p, e)| (p.to_u32().unwrap(), e))
+            .collect();
+        assert_eq!(
+            factors,
+            vec![(2, 1), (3, 4), (61, 1), (139, 1), (709, 1), (1129, 1)]
+        );
+        assert_eq!(factor_smooth(&(N_RHO.clone() - 1u32), 1 << 16), None);
+    }
+
+    #[test]
+    fn pohlig_hellman_mod_p() {
+        // 3 is a primitive root
+        let g = ModN::<N_SMOOTH>::new(3u32);
+        let n = N_SMOOTH.clone() - 1u32;
+        let factors = factor_smooth(&n, 2000).unwrap();
+        for _ in 0..4 {
+            let x = rand::thread_rng().gen_biguint_below(&n);
+            let h = Group::pow(&g, &x);
+            assert_eq!(pohlig_hellman(&g, &h, &factors), Some(x));
+        }
+    }
+
+    #[test]
+    fn pohlig_hellman_curve() {
+        let g = Point::new(BigUint::from(4u32), BigUint::from(1777152513u32))
+            .ok()
+            .unwrap();
+        let n = N_CURVE.clone() + 1u32;
+        assert_eq!(Group::pow(&g, &n), Point::new_zero());
+        let factors = factor_smooth(&n, 1000).unwrap();
+        for _ in 0..4 {
+            let x = rand::thread_rng().gen_biguint_below(&n);
+            let h = Group::pow(&g, &x);
+            assert_eq!(pohlig_hellman(&g, &h, &factors), Some(x));
+        }
+    }
+
+    #[test]
+    fn rho_mod_p() {
+        // g has prime order q = (p - 1) / 224, which is too big for Pohlig-Hellman to help
+        let g = ModN::<N_RHO>::new(3143457389u32);
+        let q = BigUint::from(4908534053u64);
+        let x = rand::thread_rng().gen_biguint_below(&q);
+        let h = Group::pow(&g, &x);
+        assert_eq!(pollard_rho(&g, &h, &q, 8), Some(x));
+    }
+
+    #[test]
+    fn rho_curve() {
+        // The subgroup of order 859
+        let g = Point::new(BigUint::from(4u32), BigUint::from(1777152513u32))
+            .ok()
+            .unwrap();
+        let g = Group::pow(&g, &BigUint::from(4294967358u64 / 859));
+        let q = BigUint::from(859u32);
+        for _ in 0..4 {
+            let x = rand::thread_rng().gen_biguint_below(&q);
+            let h = Group::pow(&g, &x);
+            assert_eq!(pollard_rho(&g, &h, &q, 8), Some(x.clone()));
+            assert_eq!(bsgs(&g, &h, &q), Some(x));
+        }
+    }
+
+    #[test]
+    fn curve_inverse() {
+        let g = Point::new(BigUint::from(3u32), BigUint::from(1250610845u32))
+            .ok()
+            .unwrap();
+        assert_eq!(g.op(&g.inverse()), Point::new_zero());
+        // (N_CURVE - 1, 0) has order 2, so it's its own inverse
+        let t = Point::new(N_CURVE.clone() - 1u32, BigUint::from(0u32))
+            .ok()
+            .unwrap();
+        assert_eq!(t.inverse(), t);
+        assert_eq!(t.op(&t), Point::new_zero());
+    }
+}
@@ -1,10 +1,15 @@
 mod aes;
+pub mod blowfish;
+pub mod cbc_mac;
 pub mod diffie;
+pub mod dlog;
 pub mod rsa;
 pub mod stream;
+pub mod subgroup;
 pub mod eliptic_curve;
 pub mod util;
 pub mod modulus;
+pub mod pkcs1;
 pub mod vigenere;
 pub mod psuedorandom;
 mod feistel;
@@ -4,7 +4,7 @@
 // Distributed under terms of the MIT license.
 //
 
-use std::{cmp::Ordering, marker::PhantomData, str::FromStr, fmt::Debug};
+use std::{cmp::Ordering, marker::PhantomData, str::FromStr, fmt::Debug, hash::Hash};
 
 use num_bigint::BigUint;
 
@@ -37,6 +37,21 @@ prime_mod!(
             "57896044618658097711785492504343953926634992332820282019728792003956564819949"
 );
 
+// Small primes with known factorisations, for testing the discrete log algorithms in `dlog`
+#[cfg(test)]
+prime_mod!(
+    // p - 1 = 2 * 3^4 * 61 * 139 * 709 * 1129
+    N_SMOOTH => "1099511628679",
+    // p - 1 = 2^5 * 7 * 4908534053
+    N_RHO => "1099511627873",
+    // p = 2 mod 3, so y^2 = x^3 + 1 has p + 1 = 2 * 3 * 11^2 * 71 * 97 * 859 points
+    N_CURVE => "4294967357",
+    // The group from challenge 58, where g has order q = 335062023296420808191071248367701059461
+    N_CH58 =>
+            "11470374874925275658116663507232161402086650258453896274534991676898999262641581519\
+            101074740642369848233294239851519212341844337347119899874391456329785623"
+);
+
 //lazy_static::lazy_static! {
 //pub static ref N_5: BigUint = BigUint::from_str("5").unwrap();
 //}
@@ -70,6 +85,11 @@ impl<M: PrimeMod> Ord for ModN<M> {
         self.0.cmp(&rhs.0)
     }
 }
+impl<M: PrimeMod> Hash for ModN<M> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
 impl<M: PrimeMod> Debug for ModN<M> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -180,14 +200,15 @@ impl_op!(ModN, Div, div, M, lhs, rhs => (&lhs.0 * rhs.clone().mul_inverse().0) %
 impl<M: PrimeMod> std::ops::Neg for ModN<M> {
     type Output = Self;
     fn neg(self) -> Self::Output {
-        Self(M::get_modulus() - self.0, PhantomData)
+        // Zero has to stay 0 rather than become M
+        Self((M::get_modulus() - self.0) % M::get_modulus(), PhantomData)
     }
 }
 
 impl<M: PrimeMod> std::ops::Neg for &ModN<M> {
     type Output = ModN<M>;
     fn neg(self) -> Self::Output {
-        ModN(M::get_modulus() - &self.0, PhantomData)
+        ModN((M::get_modulus() - &self.0) % M::get_modulus(), PhantomData)
     }
 }
 
@@ -250,6 +271,20 @@ impl<M: PrimeMod, E: ElipticCurve<M>> Debug for CurvePoint<M, E> {
     }
 }
 
+impl<M: PrimeMod, E: ElipticCurve<M>> PartialEq for CurvePoint<M, E> {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.coords() == rhs.coords()
+    }
+}
+
+impl<M: PrimeMod, E: ElipticCurve<M>> Eq for CurvePoint<M, E> {}
+
+impl<M: PrimeMod, E: ElipticCurve<M>> Hash for CurvePoint<M, E> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.coords().hash(state)
+    }
+}
+
 impl<M: PrimeMod, E: ElipticCurve<M>> Clone for CurvePoint<M, E> {
     fn clone(&self) -> Self {
         match self {
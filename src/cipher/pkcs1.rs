@@ -0,0 +1,836 @@
+//
+// pkcs1.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! PKCS #1 padding (RFC 8017) for `RSAKey`
+//!
+//! Textbook RSA is deterministic and malleable, so messages are padded to the size of n first:
+//!
+//! v1.5 encryption: 00 02 || nonzero random bytes (at least 8) || 00 || M
+//! v1.5 signatures: 00 01 || FF ... FF || 00 || DigestInfo || H(M)
+//! OAEP:            00 || seed ^ MGF(maskedDB) || DB ^ MGF(seed)
+//!                  where DB = H(L) || 00 ... 00 || 01 || M
+//! PSS signatures:  DB ^ MGF(H') || H' || BC, where H' = H(00 * 8 || H(M) || salt)
+//!                  and DB = 00 ... 00 || 01 || salt
+//!
+//! Each has a strict parser, and a lenient one that makes the mistakes real implementations have,
+//! for the attacks on them: Bleichenbacher's padding oracle (challenges 47 and 48), his e = 3
+//! signature forgery (challenge 42), and Manger's attack on OAEP. PSS has no lenient parser, since
+//! it's there to compare against v1.5.
+
+use std::fmt;
+
+use num_bigint::BigUint;
+
+use super::rsa::RSAKey;
+use crate::data::Bytes;
+use crate::hash::{
+    md5::Md5,
+    sha1::Sha1,
+    sha2::{Sha224, Sha256, Sha384, Sha512},
+    Digest,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// The message is too long to pad to the size of n, or n is too short for the hash
+    MessageTooLong,
+    /// The ciphertext or signature is the wrong length, or isn't less than n
+    OutOfRange,
+    /// Bad padding. The strict parsers return this for every check, so they don't say which failed
+    Decryption,
+    /// Only from `decrypt_oaep_lenient`, when the first byte isn't zero
+    LeadingByte,
+    /// The signature doesn't match the message
+    Verification,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MessageTooLong => write!(f, "message too long"),
+            Error::OutOfRange => write!(f, "integer out of range"),
+            Error::Decryption => write!(f, "decryption error"),
+            Error::LeadingByte => write!(f, "first byte not zero"),
+            Error::Verification => write!(f, "invalid signature"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A hash with an ASN.1 object identifier, for v1.5 signatures
+pub trait DigestInfo: Digest {
+    /// The DER encoding of the DigestInfo, up to the hash itself
+    const PREFIX: &'static [u8];
+}
+
+impl DigestInfo for Md5 {
+    const PREFIX: &'static [u8] = &[
+        0x30, 0x20, 0x30, 0x0c, 0x06, 0x08, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x05, 0x05,
+        0x00, 0x04, 0x10,
+    ];
+}
+
+impl DigestInfo for Sha1 {
+    const PREFIX: &'static [u8] = &[
+        0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14,
+    ];
+}
+
+impl DigestInfo for Sha224 {
+    const PREFIX: &'static [u8] = &[
+        0x30, 0x2d, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x04,
+        0x05, 0x00, 0x04, 0x1c,
+    ];
+}
+
+impl DigestInfo for Sha256 {
+    const PREFIX: &'static [u8] = &[
+        0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01,
+        0x05, 0x00, 0x04, 0x20,
+    ];
+}
+
+impl DigestInfo for Sha384 {
+    const PREFIX: &'static [u8] = &[
+        0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02,
+        0x05, 0x00, 0x04, 0x30,
+    ];
+}
+
+impl DigestInfo for Sha512 {
+    const PREFIX: &'static [u8] = &[
+        0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03,
+        0x05, 0x00, 0x04, 0x40,
+    ];
+}
+
+/// MGF1: H(seed || 0) || H(seed || 1) || ..., truncated to `len`
+pub fn mgf1<H: Digest>(seed: &[u8], len: usize) -> Bytes {
+    let mut mask = Bytes::with_capacity(len + H::OUTPUT_SIZE);
+    let mut counter = 0u32;
+    while mask.len() < len {
+        let mut hasher = H::default();
+        hasher.update(seed);
+        hasher.update(&counter.to_be_bytes());
+        mask += hasher.finalize();
+        counter += 1;
+    }
+    mask.truncate(len)
+}
+
+/// Applies the public key to a padded message
+fn public_op(key: &RSAKey, em: &Bytes) -> Bytes {
+    let c = key.encrypt(em.to_biguint());
+    Bytes::from_biguint(&c, key.size()).unwrap()
+}
+
+/// Applies the private key to a ciphertext, after checking it's less than n
+fn private_op(key: &RSAKey, c: &[u8]) -> Result<Bytes, Error> {
+    let c = checked_integer(key, c)?;
+    Ok(Bytes::from_biguint(&key.decrypt(c), key.size()).unwrap())
+}
+
+fn checked_integer(key: &RSAKey, c: &[u8]) -> Result<BigUint, Error> {
+    let c = BigUint::from_bytes_be(c);
+    if c >= *key.modulus() {
+        return Err(Error::OutOfRange);
+    }
+    Ok(c)
+}
+
+/// RSAES-PKCS1-v1_5 encryption
+pub fn encrypt_v15(key: &RSAKey, message: &[u8]) -> Result<Bytes, Error> {
+    let k = key.size();
+    if message.len() + 11 > k {
+        return Err(Error::MessageTooLong);
+    }
+    let padding: Bytes = std::iter::repeat_with(rand::random::<u8>)
+        .filter(|&b| b != 0)
+        .take(k - message.len() - 3)
+        .collect();
+    let em = ((Bytes::from_bytes(&[0, 2]) + padding) + 0u8) + message;
+    Ok(public_op(key, &em))
+}
+
+/// RSAES-PKCS1-v1_5 decryption, checking all of the padding
+pub fn decrypt_v15(key: &RSAKey, ciphertext: &[u8]) -> Result<Bytes, Error> {
+    if ciphertext.len() != key.size() || key.size() < 11 {
+        return Err(Error::Decryption);
+    }
+    let em = private_op(key, ciphertext).map_err(|_| Error::Decryption)?;
+    let separator = em.iter().skip(2).position(|&b| b == 0).map(|i| i + 2);
+    match separator {
+        // At least 8 bytes of padding
+        Some(i) if em[0] == 0 && em[1] == 2 && i >= 10 => Ok(Bytes::from_bytes(&em[i + 1..])),
+        _ => Err(Error::Decryption),
+    }
+}
+
+/// RSAES-PKCS1-v1_5 decryption that only checks for 00 02 at the start
+///
+/// The message is whatever comes after the next zero, or nothing if there isn't one. Whether this
+/// succeeds is the padding oracle for Bleichenbacher's attack.
+pub fn decrypt_v15_lenient(key: &RSAKey, ciphertext: &[u8]) -> Result<Bytes, Error> {
+    let em = private_op(key, ciphertext)?;
+    if em.len() < 2 || em[0] != 0 || em[1] != 2 {
+        return Err(Error::Decryption);
+    }
+    let message = match em.iter().skip(2).position(|&b| b == 0) {
+        Some(i) => Bytes::from_bytes(&em[i + 3..]),
+        None => Bytes::new(),
+    };
+    Ok(message)
+}
+
+/// EMSA-PKCS1-v1_5 encoding of a message to `k` bytes
+pub fn encode_v15_signature<H: DigestInfo>(message: &[u8], k: usize) -> Result<Bytes, Error> {
+    let t = Bytes::from_bytes(H::PREFIX) + H::digest(message);
+    if t.len() + 11 > k {
+        return Err(Error::MessageTooLong);
+    }
+    let padding = Bytes::from_bytes(&vec![0xFF; k - t.len() - 3]);
+    Ok(((Bytes::from_bytes(&[0, 1]) + padding) + 0u8) + t)
+}
+
+/// RSASSA-PKCS1-v1_5 signature
+pub fn sign_v15<H: DigestInfo>(key: &RSAKey, message: &[u8]) -> Result<Bytes, Error> {
+    let em = encode_v15_signature::<H>(message, key.size())?;
+    let s = key.encrypt_sign(em.to_biguint());
+    Ok(Bytes::from_biguint(&s, key.size()).unwrap())
+}
+
+/// The encoded message inside a signature
+fn open_signature(key: &RSAKey, signature: &[u8]) -> Result<Bytes, Error> {
+    if signature.len() != key.size() {
+        return Err(Error::OutOfRange);
+    }
+    let s = checked_integer(key, signature)?;
+    Ok(Bytes::from_biguint(&key.decrypt_sign(s), key.size()).unwrap())
+}
+
+/// RSASSA-PKCS1-v1_5 verification, by encoding the message and comparing the whole thing
+pub fn verify_v15<H: DigestInfo>(
+    key: &RSAKey,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    let em = open_signature(key, signature)?;
+    let expected = encode_v15_signature::<H>(message, key.size())?;
+    if em.ct_eq(&expected) {
+        Ok(())
+    } else {
+        Err(Error::Verification)
+    }
+}
+
+/// RSASSA-PKCS1-v1_5 verification that parses the padding, rather than checking its length
+///
+/// It skips any number of FF bytes, then checks the DigestInfo and hash, and ignores anything
+/// after them. With e = 3, that leaves room to forge a signature by taking a cube root.
+pub fn verify_v15_lenient<H: DigestInfo>(
+    key: &RSAKey,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    let em = open_signature(key, signature)?;
+    if em.len() < 2 || em[0] != 0 || em[1] != 1 {
+        return Err(Error::Verification);
+    }
+    let rest = &em[2..];
+    let ff = rest.iter().take_while(|&&b| b == 0xFF).count();
+    let rest = &rest[ff..];
+    let t = Bytes::from_bytes(H::PREFIX) + H::digest(message);
+    if rest.first() == Some(&0) && rest[1..].starts_with(&t) {
+        Ok(())
+    } else {
+        Err(Error::Verification)
+    }
+}
+
+/// RSAES-OAEP encryption, with MGF1 over the same hash
+pub fn encrypt_oaep<H: Digest>(key: &RSAKey, message: &[u8], label: &[u8]) -> Result<Bytes, Error> {
+    let (k, h_len) = (key.size(), H::OUTPUT_SIZE);
+    if message.len() + 2 * h_len + 2 > k {
+        return Err(Error::MessageTooLong);
+    }
+    let padding = Bytes::zero(k - message.len() - 2 * h_len - 2);
+    let db = ((H::digest(label) + padding) + 1u8) + message;
+    let seed = Bytes::rand(h_len);
+    let masked_db = db ^ mgf1::<H>(&seed, k - h_len - 1);
+    let masked_seed = seed ^ mgf1::<H>(&masked_db, h_len);
+    let em = (Bytes::zero(1) + masked_seed) + masked_db;
+    Ok(public_op(key, &em))
+}
+
+/// Splits an OAEP encoded message into its first byte, H(L) and the rest of DB
+fn unmask_oaep<H: Digest>(em: &Bytes) -> (u8, Bytes, Bytes) {
+    let h_len = H::OUTPUT_SIZE;
+    let (masked_seed, masked_db) = (&em[1..h_len + 1], &em[h_len + 1..]);
+    let seed = Bytes::from_bytes(masked_seed) ^ mgf1::<H>(masked_db, h_len);
+    let db = Bytes::from_bytes(masked_db) ^ mgf1::<H>(&seed, masked_db.len());
+    let (l_hash, rest) = (
+        Bytes::from_bytes(&db[..h_len]),
+        Bytes::from_bytes(&db[h_len..]),
+    );
+    (em[0], l_hash, rest)
+}
+
+/// Where the 01 after the zeros is, if there's nothing else in the way
+fn oaep_separator(rest: &Bytes) -> Option<usize> {
+    let i = rest.iter().position(|&b| b != 0)?;
+    if rest[i] == 1 {
+        Some(i)
+    } else {
+        None
+    }
+}
+
+/// RSAES-OAEP decryption, which checks everything before deciding whether to fail
+pub fn decrypt_oaep<H: Digest>(
+    key: &RSAKey,
+    ciphertext: &[u8],
+    label: &[u8],
+) -> Result<Bytes, Error> {
+    let (k, h_len) = (key.size(), H::OUTPUT_SIZE);
+    if ciphertext.len() != k || k < 2 * h_len + 2 {
+        return Err(Error::Decryption);
+    }
+    let em = private_op(key, ciphertext).map_err(|_| Error::Decryption)?;
+    let (y, l_hash, rest) = unmask_oaep::<H>(&em);
+    let label_ok = l_hash.ct_eq(&H::digest(label));
+    match oaep_separator(&rest) {
+        Some(i) if y == 0 && label_ok => Ok(Bytes::from_bytes(&rest[i + 1..])),
+        _ => Err(Error::Decryption),
+    }
+}
+
+/// RSAES-OAEP decryption that reports a nonzero first byte as `LeadingByte`, and doesn't check
+/// the label
+///
+/// Telling the two failures apart is the oracle for Manger's attack.
+pub fn decrypt_oaep_lenient<H: Digest>(key: &RSAKey, ciphertext: &[u8]) -> Result<Bytes, Error> {
+    if key.size() < 2 * H::OUTPUT_SIZE + 2 {
+        return Err(Error::Decryption);
+    }
+    let em = private_op(key, ciphertext)?;
+    let (y, _, rest) = unmask_oaep::<H>(&em);
+    if y != 0 {
+        return Err(Error::LeadingByte);
+    }
+    let i = oaep_separator(&rest).ok_or(Error::Decryption)?;
+    Ok(Bytes::from_bytes(&rest[i + 1..]))
+}
+
+/// How long a PSS salt is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaltLength {
+    /// As long as the hash, which is what's usually used
+    Digest,
+    /// As long as will fit
+    Max,
+    Exact(usize),
+    /// Works out the length from the padding when verifying, and is `Digest` when signing
+    Auto,
+}
+
+impl SaltLength {
+    /// The length for an encoded message of `em_len` bytes, or `None` for `Auto` when verifying
+    fn resolve(self, h_len: usize, em_len: usize) -> Option<usize> {
+        match self {
+            SaltLength::Digest => Some(h_len),
+            SaltLength::Max => Some(em_len.saturating_sub(h_len + 2)),
+            SaltLength::Exact(len) => Some(len),
+            SaltLength::Auto => None,
+        }
+    }
+}
+
+/// H(00 * 8 || H(M) || salt), which the signature is over
+fn pss_hash<H: Digest>(m_hash: &[u8], salt: &[u8]) -> Bytes {
+    let mut hasher = H::default();
+    hasher.update(&[0; 8]);
+    hasher.update(m_hash);
+    hasher.update(salt);
+    hasher.finalize()
+}
+
+/// EMSA-PSS encoding, with MGF1 over the same hash, to a message of `em_bits` bits
+pub fn encode_pss<H: Digest>(message: &[u8], em_bits: usize, salt: &[u8]) -> Result<Bytes, Error> {
+    let (em_len, h_len) = (em_bits.div_ceil(8), H::OUTPUT_SIZE);
+    if em_len < h_len + salt.len() + 2 {
+        return Err(Error::MessageTooLong);
+    }
+    let h = pss_hash::<H>(&H::digest(message), salt);
+    let db = (Bytes::zero(em_len - salt.len() - h_len - 2) + 1u8) + salt;
+    let mut masked_db = db ^ mgf1::<H>(&h, em_len - h_len - 1);
+    // Clear the bits above em_bits, so the message is less than n
+    masked_db[0] &= 0xFF >> (8 * em_len - em_bits);
+    Ok((masked_db + h) + 0xBCu8)
+}
+
+/// RSASSA-PSS signature with a random salt
+pub fn sign_pss<H: Digest>(
+    key: &RSAKey,
+    message: &[u8],
+    salt_len: SaltLength,
+) -> Result<Bytes, Error> {
+    let em_bits = key.modulus().bits() as usize - 1;
+    let salt_len = match salt_len {
+        SaltLength::Auto => H::OUTPUT_SIZE,
+        len => len.resolve(H::OUTPUT_SIZE, em_bits.div_ceil(8)).unwrap(),
+    };
+    let em = encode_pss::<H>(message, em_bits, &Bytes::rand(salt_len))?;
+    let s = key.encrypt_sign(em.to_biguint());
+    Ok(Bytes::from_biguint(&s, key.size()).unwrap())
+}
+
+/// EMSA-PSS verification of an encoded message of `em_bits` bits
+pub fn verify_pss_encoding<H: Digest>(
+    message: &[u8],
+    em: &Bytes,
+    em_bits: usize,
+    salt_len: SaltLength,
+) -> Result<(), Error> {
+    let (em_len, h_len) = (em_bits.div_ceil(8), H::OUTPUT_SIZE);
+    let salt_len = salt_len.resolve(h_len, em_len);
+    if em.len() != em_len || em_len < h_len + salt_len.unwrap_or(0) + 2 {
+        return Err(Error::Verification);
+    }
+    // The bits above em_bits have to be clear
+    let unused = 8 * em_len - em_bits;
+    if em[em_len - 1] != 0xBC || (unused > 0 && em[0] >> (8 - unused) != 0) {
+        return Err(Error::Verification);
+    }
+    let (masked_db, h) = (
+        &em[..em_len - h_len - 1],
+        &em[em_len - h_len - 1..em_len - 1],
+    );
+    let mut db = Bytes::from_bytes(masked_db) ^ mgf1::<H>(h, masked_db.len());
+    db[0] &= 0xFF >> unused;
+    // The 01 before the salt, which is where the first nonzero byte should be
+    let one = db.iter().position(|&b| b != 0).ok_or(Error::Verification)?;
+    let expected = salt_len.map(|len| db.len() - len - 1);
+    if db[one] != 1 || expected.is_some_and(|i| i != one) {
+        return Err(Error::Verification);
+    }
+    let salt = &db[one + 1..];
+    if pss_hash::<H>(&H::digest(message), salt).ct_eq(&Bytes::from_bytes(h)) {
+        Ok(())
+    } else {
+        Err(Error::Verification)
+    }
+}
+
+/// RSASSA-PSS verification
+pub fn verify_pss<H: Digest>(
+    key: &RSAKey,
+    message: &[u8],
+    signature: &[u8],
+    salt_len: SaltLength,
+) -> Result<(), Error> {
+    let em_bits = key.modulus().bits() as usize - 1;
+    let em = open_signature(key, signature)?;
+    // em is k bytes, which can be one more than the encoding needs
+    let extra = em.len() - em_bits.div_ceil(8);
+    if em[..extra].iter().any(|&b| b != 0) {
+        return Err(Error::Verification);
+    }
+    verify_pss_encoding::<H>(message, &Bytes::from_bytes(&em[extra..]), em_bits, salt_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1024 bit key, generated along with the vectors below by another implementation
+    fn test_key() -> RSAKey {
+        let p = BigUint::parse_bytes(
+            b"ddabc925e0a2f198c50629725d27a40432a0b87a98648a3dae4cd43888f4641cb97931faed7eaa1af93083ed\
+              43c226b5d209d37a5764fd6f71178a97ed2b7131",
+            16,
+        )
+        .unwrap();
+        let q = BigUint::parse_bytes(
+            b"d3bf9f09a9359abb162e7aacd80e5d69c4ed4255df9ffff28a4485c34f39a09fddf4ac98b54d3b0a020f2902\
+              810f181daf491ae48a755c719074dc639da8ed35",
+            16,
+        )
+        .unwrap();
+        RSAKey::from_primes(p, q, 65537u32.into()).unwrap()
+    }
+
+    const MESSAGE: &[u8] = b"hello, world";
+
+    #[test]
+    fn integer_conversion() {
+        let n = BigUint::from(0x0102u32);
+        assert_eq!(
+            Bytes::from_biguint(&n, 4),
+            Some(Bytes::from_bytes(&[0, 0, 1, 2]))
+        );
+        assert_eq!(Bytes::from_biguint(&n, 1), None);
+        assert_eq!(
+            Bytes::from_biguint(&BigUint::from(0u32), 2),
+            Some(Bytes::zero(2))
+        );
+        assert_eq!(Bytes::from_bytes(&[0, 0, 1, 2]).to_biguint(), n);
+    }
+
+    #[test]
+    fn mgf() {
+        // The first block is just H(seed || 00000000)
+        let mask = mgf1::<Sha1>(b"seed", 50);
+        assert_eq!(mask.len(), 50);
+        assert_eq!(mask.truncate(20), Sha1::digest(b"seed\x00\x00\x00\x00"));
+        assert_eq!(mgf1::<Sha1>(b"seed", 10), mask.truncate(10));
+    }
+
+    #[test]
+    fn v15_encryption() {
+        let key = test_key();
+        let c = Bytes::read_hex(
+            "6727043c8f3969266e5853ae15bf6a9132078afa536889dff146541c06ba13b74a48d75facfd8f4b\
+             9d8168f84e4e75f74c1c97c1f58be7df9eb258980b9fa3f1df2ce2afb47f679756df4b032c640b2b\
+             4b09a63f3e576f2c876ba76a1c2cb2b8aba473d8384778a26b876e4cf012f24de5560d648864e8f1\
+             d859eab809730dae",
+        );
+        assert_eq!(decrypt_v15(&key, &c), Ok(Bytes::from_bytes(MESSAGE)));
+
+        let c = encrypt_v15(&key, MESSAGE).unwrap();
+        assert_eq!(c.len(), 128);
+        // Random padding, so encrypting twice gives different ciphertexts
+        assert_ne!(c, encrypt_v15(&key, MESSAGE).unwrap());
+        assert_eq!(decrypt_v15(&key, &c), Ok(Bytes::from_bytes(MESSAGE)));
+        assert_eq!(
+            decrypt_v15_lenient(&key, &c),
+            Ok(Bytes::from_bytes(MESSAGE))
+        );
+        assert_eq!(encrypt_v15(&key, &[0; 118]), Err(Error::MessageTooLong));
+        assert!(encrypt_v15(&key, &[0; 117]).is_ok());
+    }
+
+    #[test]
+    fn v15_lenient_padding() {
+        let key = test_key();
+        let encrypt = |em: Bytes| Bytes::from_biguint(&key.encrypt(em.to_biguint()), 128).unwrap();
+        // Only 4 bytes of padding
+        let em = Bytes::from_bytes(&[0, 2, 1, 1, 1, 1, 0]) + &[7u8; 121][..];
+        assert_eq!(
+            decrypt_v15(&key, &encrypt(em.clone())),
+            Err(Error::Decryption)
+        );
+        assert_eq!(
+            decrypt_v15_lenient(&key, &encrypt(em)),
+            Ok(Bytes::from_bytes(&[7; 121]))
+        );
+        // No separator
+        let em = Bytes::from_bytes(&[0, 2]) + &[7u8; 126][..];
+        assert_eq!(
+            decrypt_v15(&key, &encrypt(em.clone())),
+            Err(Error::Decryption)
+        );
+        assert_eq!(decrypt_v15_lenient(&key, &encrypt(em)), Ok(Bytes::new()));
+        let em = Bytes::from_bytes(&[0, 1]) + &[7u8; 126][..];
+        assert_eq!(
+            decrypt_v15_lenient(&key, &encrypt(em)),
+            Err(Error::Decryption)
+        );
+        // Not less than n
+        assert_eq!(
+            decrypt_v15_lenient(&key, &[0xFF; 128]),
+            Err(Error::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn v15_signatures() {
+        let key = test_key();
+        let sig = Bytes::read_hex(
+            "117f29f5e5949dac95aaf8be8a30ffc345e838e3617dcb6be0f6a17fd9c0357bd0bc7b7e76e56014\
+             c466d022b0b409e484a6fdb6dbc4c36f1fea7b07aabb6f94048542012d06c6e6a2cb948100a9a3de\
+             7c63bf3c3176d5b78e2a669b540addf5e123946fb8046cb63af468cf55ea1c8dfc252d0495a401a1\
+             4e70e53b1cf3c375",
+        );
+        assert_eq!(sign_v15::<Sha256>(&key, MESSAGE), Ok(sig.clone()));
+        assert_eq!(verify_v15::<Sha256>(&key, MESSAGE, &sig), Ok(()));
+        assert_eq!(verify_v15_lenient::<Sha256>(&key, MESSAGE, &sig), Ok(()));
+        assert_eq!(
+            verify_v15::<Sha256>(&key, b"hello, world!", &sig),
+            Err(Error::Verification)
+        );
+        assert_eq!(
+            verify_v15::<Sha1>(&key, MESSAGE, &sig),
+            Err(Error::Verification)
+        );
+
+        let sig = Bytes::read_hex(
+            "099304a3e569572c109959f47aa80fca8dfefcc0fb540e5b6d49c0c716b6a0dfc6a49edf8b3ddcf2\
+             98bc7a5ad2a87e055884a844675b7bb9057a2787416355a39923cc8f79b56d22e2ed1bb21f34f7e7\
+             64c15f6166682fb1e3185cadec8d8918f4a21bbf24c7ce86db8d029f55861ed4be8f758d60c0d257\
+             b71a3d523f2a5e51",
+        );
+        assert_eq!(sign_v15::<Sha1>(&key, MESSAGE), Ok(sig.clone()));
+        assert_eq!(verify_v15::<Sha1>(&key, MESSAGE, &sig), Ok(()));
+        let public = key.public_key_part();
+        assert_eq!(verify_v15::<Sha1>(&public, MESSAGE, &sig), Ok(()));
+        assert_eq!(
+            verify_v15::<Sha1>(&public, MESSAGE, &sig[1..]),
+            Err(Error::OutOfRange)
+        );
+
+        let sig = sign_v15::<Sha512>(&key, MESSAGE).unwrap();
+        assert_eq!(verify_v15::<Sha512>(&key, MESSAGE, &sig), Ok(()));
+    }
+
+    #[test]
+    fn v15_lenient_signatures() {
+        let key = test_key();
+        // Short padding with garbage after the hash gets past the lenient check only
+        let t = Bytes::from_bytes(Sha256::PREFIX) + Sha256::digest(MESSAGE);
+        let em = ((Bytes::from_bytes(&[0, 1, 0xFF, 0]) + t) + &[0x42u8; 73][..]).truncate(128);
+        let forged = key.encrypt_sign(em.to_biguint());
+        let forged = Bytes::from_biguint(&forged, 128).unwrap();
+        assert_eq!(verify_v15_lenient::<Sha256>(&key, MESSAGE, &forged), Ok(()));
+        assert_eq!(
+            verify_v15::<Sha256>(&key, MESSAGE, &forged),
+            Err(Error::Verification)
+        );
+        assert_eq!(
+            verify_v15_lenient::<Sha256>(&key, b"other", &forged),
+            Err(Error::Verification)
+        );
+    }
+
+    #[test]
+    fn oaep() {
+        let key = test_key();
+        let c = Bytes::read_hex(
+            "a83581ff1dc43dca4238430fdf45985ba0896f6684e66099bcd46b721f03c47a9b18686782edbc8f\
+             993cb480e00051b9df69aa0325c61f0a51fc3ee1579a5626f353d94463ca9f1db2113124dc6717e1\
+             9c91ad51bb541796014d21f2d81d6f4588e01c638d054dfe6b702f6f22fc275a29f9e68618096d0f\
+             f2d0ebcac93212ab",
+        );
+        assert_eq!(
+            decrypt_oaep::<Sha1>(&key, &c, b""),
+            Ok(Bytes::from_bytes(MESSAGE))
+        );
+        assert_eq!(
+            decrypt_oaep::<Sha256>(&key, &c, b""),
+            Err(Error::Decryption)
+        );
+
+        let c = Bytes::read_hex(
+            "b0e9c43ddf2a8771f14f01283fc6ceeadc681a32dc6f77766ce065aa19c2beb824d187ea2444afb9\
+             7c4c7aea7c2822cf5f205316f9c7484fbc0ecb023771243626fa5f2045e5d8163fac6f14316cd41e\
+             11ec678ab82c79caf3c94bce187540995880a1d582f9d78847f08f72b7847feae6ca090beefec4f3\
+             351998edf6d38663",
+        );
+        assert_eq!(
+            decrypt_oaep::<Sha256>(&key, &c, b"label"),
+            Ok(Bytes::from_bytes(MESSAGE))
+        );
+        assert_eq!(
+            decrypt_oaep::<Sha256>(&key, &c, b""),
+            Err(Error::Decryption)
+        );
+        // The lenient parser doesn't care about the label
+        assert_eq!(
+            decrypt_oaep_lenient::<Sha256>(&key, &c),
+            Ok(Bytes::from_bytes(MESSAGE))
+        );
+
+        let c = encrypt_oaep::<Sha256>(&key, MESSAGE, b"").unwrap();
+        assert_eq!(
+            decrypt_oaep::<Sha256>(&key, &c, b""),
+            Ok(Bytes::from_bytes(MESSAGE))
+        );
+        assert_eq!(
+            encrypt_oaep::<Sha256>(&key, &[0; 63], b""),
+            Err(Error::MessageTooLong)
+        );
+        let c = encrypt_oaep::<Sha256>(&key, &[0; 62], b"").unwrap();
+        assert_eq!(decrypt_oaep::<Sha256>(&key, &c, b""), Ok(Bytes::zero(62)));
+    }
+
+    #[test]
+    fn oaep_leading_byte() {
+        let key = test_key();
+        // Anything that decrypts to 00 01 ... has a nonzero first byte
+        let c = key.encrypt(BigUint::from(1u32) << 1016);
+        let c = Bytes::from_biguint(&c, 128).unwrap();
+        assert_eq!(
+            decrypt_oaep_lenient::<Sha1>(&key, &c),
+            Err(Error::LeadingByte)
+        );
+        assert_eq!(decrypt_oaep::<Sha1>(&key, &c, b""), Err(Error::Decryption));
+        // Small enough to start with 00, but with garbage in the padding
+        let c = Bytes::from_biguint(&key.encrypt(BigUint::from(12345u32)), 128).unwrap();
+        assert_eq!(
+            decrypt_oaep_lenient::<Sha1>(&key, &c),
+            Err(Error::Decryption)
+        );
+    }
+
+    #[test]
+    fn pss_vectors() {
+        let key = test_key();
+        // An empty salt makes the signature deterministic
+        let sig = Bytes::read_hex(
+            "0b0f5dbe57826829eb2fc6ae035ceda638bf79dcf8ba3189d69c37e17c1a490249c264aa3e2d79c3\
+             b48c3bedf617fb7068d60fb62c0fd1bc17c7c73879083c6094caa3e4a9619d37d2a044e7bf5b16e4\
+             a3b95c4494d32b36dea18a93ac90bd18976ae49cbb6672146b543a4238c665551eb5662202e6abf3\
+             913d673ce52e3cb8",
+        );
+        assert_eq!(
+            sign_pss::<Sha256>(&key, MESSAGE, SaltLength::Exact(0)),
+            Ok(sig.clone())
+        );
+        assert_eq!(
+            verify_pss::<Sha256>(&key, MESSAGE, &sig, SaltLength::Exact(0)),
+            Ok(())
+        );
+        assert_eq!(
+            verify_pss::<Sha256>(&key, MESSAGE, &sig, SaltLength::Auto),
+            Ok(())
+        );
+
+        let sig = Bytes::read_hex(
+            "6cf4a51388f3e14fca502b941df473b0e9079293f585d7a14eb7962422e68155af146c1dc2465654\
+             02b77422de9bb5d2b71a0a9fe040aaf6e3589c110a2fcf73af50bf25d2816035157b276bed4ddf82\
+             b9a6c9c3ea5ce0d365ae8913e7bc33830fcbbc350f40114e91ae31e579447ecf0604ea614c40e46b\
+             8a126af0d5b9799a",
+        );
+        for len in [SaltLength::Digest, SaltLength::Exact(32), SaltLength::Auto] {
+            assert_eq!(verify_pss::<Sha256>(&key, MESSAGE, &sig, len), Ok(()));
+        }
+        for len in [SaltLength::Exact(31), SaltLength::Max] {
+            assert_eq!(
+                verify_pss::<Sha256>(&key, MESSAGE, &sig, len),
+                Err(Error::Verification)
+            );
+        }
+        assert_eq!(
+            verify_pss::<Sha256>(&key, b"hello, world!", &sig, SaltLength::Auto),
+            Err(Error::Verification)
+        );
+        assert_eq!(
+            verify_pss::<Sha1>(&key, MESSAGE, &sig, SaltLength::Auto),
+            Err(Error::Verification)
+        );
+
+        let sig = Bytes::read_hex(
+            "5b57e7e0fed3b833f9c565f13855de2a92357460820b93809ca3357f39da1b55b658a6bcbea827fc\
+             7c61dd09a323c85284756ec2ec1fd409f4e02fbbb6edcba512bf19369c546fed99f09b6fc9901d48\
+             127b92b71c32f0512f33f60fb36cfacfa95031f23f0d1621349c76635839b2106027010bb29e2b14\
+             03b0b2c1bb04aaba",
+        );
+        let public = key.public_key_part();
+        assert_eq!(
+            verify_pss::<Sha1>(&public, MESSAGE, &sig, SaltLength::Digest),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn pss_rfc_vector() {
+        // Example 1.1 from the RSASSA-PSS vectors accompanying RFC 8017 (pss-vect.txt)
+        let n = BigUint::parse_bytes(
+            b"a56e4a0e701017589a5187dc7ea841d156f2ec0e36ad52a44dfeb1e61f7ad991d8c51056ffedb162\
+              b4c0f283a12a88a394dff526ab7291cbb307ceabfce0b1dfd5cd9508096d5b2b8b6df5d671ef6377\
+              c0921cb23c270a70e2598e6ff89d19f105acc2d3f0cb35f29280e1386b6f64c4ef22e1e1f20d0ce8\
+              cffb2249bd9a2137",
+            16,
+        )
+        .unwrap();
+        let key = RSAKey::public_key(n, 65537u32.into());
+        let message = Bytes::read_hex(
+            "cdc87da223d786df3b45e0bbbc721326d1ee2af806cc315475cc6f0d9c66e1b62371d45ce2392e1a\
+             c92844c310102f156a0d8d52c1f4c40ba3aa65095786cb769757a6563ba958fed0bcc984e8b517a3\
+             d5f515b23b8a41e74aa867693f90dfb061a6e86dfaaee64472c00e5f20945729cbebe77f06ce78e0\
+             8f4098fba41f9d6193c0317e8b60d4b6084acb42d29e3808a3bc372d85e331170fcbf7cc72d0b71c\
+             296648b3a4d10f416295d0807aa625cab2744fd9ea8fd223c42537029828bd16be02546f130fd2e3\
+             3b936d2676e08aed1b73318b750a0167d0",
+        );
+        let salt = Bytes::read_hex("dee959c7e06411361420ff80185ed57f3e6776af");
+        let sig = Bytes::read_hex(
+            "9074308fb598e9701b2294388e52f971faac2b60a5145af185df5287b5ed2887e57ce7fd44dc8634\
+             e407c8e0e4360bc226f3ec227f9d9e54638e8d31f5051215df6ebb9c2f9579aa77598a38f914b5b9\
+             c1bd83c4e2f9f382a0d0aa3542ffee65984a601bc69eb28deb27dca12c82c2d4c3f66cd500f1ff2b\
+             994d8a4e30cbb33c",
+        );
+        let em = Bytes::from_biguint(&key.encrypt(sig.to_biguint()), 128).unwrap();
+        assert_eq!(encode_pss::<Sha1>(&message, 1023, &salt), Ok(em));
+        for len in [SaltLength::Digest, SaltLength::Auto] {
+            assert_eq!(verify_pss::<Sha1>(&key, &message, &sig, len), Ok(()));
+        }
+    }
+
+    #[test]
+    fn pss_salt_lengths() {
+        let key = test_key();
+        for len in [SaltLength::Digest, SaltLength::Max, SaltLength::Exact(5)] {
+            let sig = sign_pss::<Sha256>(&key, MESSAGE, len).unwrap();
+            // Random salts make every signature different
+            assert_ne!(sig, sign_pss::<Sha256>(&key, MESSAGE, len).unwrap());
+            assert_eq!(verify_pss::<Sha256>(&key, MESSAGE, &sig, len), Ok(()));
+            assert_eq!(
+                verify_pss::<Sha256>(&key, MESSAGE, &sig, SaltLength::Auto),
+                Ok(())
+            );
+        }
+        // 128 - 32 - 2 = 94 bytes of salt at most
+        let sig = sign_pss::<Sha256>(&key, MESSAGE, SaltLength::Max).unwrap();
+        assert_eq!(
+            verify_pss::<Sha256>(&key, MESSAGE, &sig, SaltLength::Exact(94)),
+            Ok(())
+        );
+        assert_eq!(
+            sign_pss::<Sha256>(&key, MESSAGE, SaltLength::Exact(95)),
+            Err(Error::MessageTooLong)
+        );
+    }
+
+    #[test]
+    fn pss_encoding() {
+        // A modulus that's a multiple of 8 bits long has an encoding a byte shorter than n
+        let em = encode_pss::<Sha1>(MESSAGE, 1023, &Bytes::rand(20)).unwrap();
+        assert_eq!(em.len(), 128);
+        assert_eq!(em[0] & 0x80, 0);
+        assert_eq!(
+            verify_pss_encoding::<Sha1>(MESSAGE, &em, 1023, SaltLength::Digest),
+            Ok(())
+        );
+        let em = encode_pss::<Sha1>(MESSAGE, 1024, &Bytes::rand(20)).unwrap();
+        assert_eq!(
+            verify_pss_encoding::<Sha1>(MESSAGE, &em, 1024, SaltLength::Digest),
+            Ok(())
+        );
+        let em = encode_pss::<Sha1>(MESSAGE, 1016, &Bytes::rand(20)).unwrap();
+        assert_eq!(em.len(), 127);
+        let mut bad = em.clone();
+        bad[126] = 0xBD;
+        assert_eq!(
+            verify_pss_encoding::<Sha1>(MESSAGE, &bad, 1016, SaltLength::Digest),
+            Err(Error::Verification)
+        );
+    }
+
+    #[test]
+    fn pss_against_v15_forgery() {
+        // The garbage after the hash that the lenient v1.5 parser ignores has nowhere to go in
+        // PSS, where every byte is checked
+        let key = test_key();
+        let t = Bytes::from_bytes(Sha256::PREFIX) + Sha256::digest(MESSAGE);
+        let em = ((Bytes::from_bytes(&[0, 1, 0xFF, 0]) + t) + &[0x42u8; 73][..]).truncate(128);
+        let forged = Bytes::from_biguint(&key.encrypt_sign(em.to_biguint()), 128).unwrap();
+        assert_eq!(verify_v15_lenient::<Sha256>(&key, MESSAGE, &forged), Ok(()));
+        assert_eq!(
+            verify_pss::<Sha256>(&key, MESSAGE, &forged, SaltLength::Auto),
+            Err(Error::Verification)
+        );
+    }
+}
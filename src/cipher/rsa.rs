@@ -1,39 +1,111 @@
-use num_bigint::{BigUint, RandBigInt};
+use num_bigint::{BigInt, BigUint, RandBigInt};
 use rand::prelude::*;
-use rayon::iter::{plumbing::UnindexedProducer, IntoParallelIterator, ParallelIterator};
 use std::ops::Range;
 
+use super::util::extended_euclid;
+use crate::fermat::{is_prime, SMALL_PRIMES};
+
+/// The public exponent used unless another is asked for
+pub const DEFAULT_EXPONENT: u32 = 65537;
+
 #[derive(Debug)]
 pub struct RSAKey {
     n: BigUint,
     p: BigUint,
     s: Option<BigUint>,
+    crt: Option<CrtParams>,
+}
+
+/// The factors of n with d mod p - 1, d mod q - 1, and q^-1 mod p, which let the private key
+/// operation be done mod p and q separately, about 4 times faster than mod n
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrtParams {
+    pub p: BigUint,
+    pub q: BigUint,
+    pub dp: BigUint,
+    pub dq: BigUint,
+    pub qinv: BigUint,
 }
 
 impl RSAKey {
     /// Create public key variant
     pub fn public_key(n: BigUint, p: BigUint) -> Self {
-        Self { n, p, s: None }
+        Self {
+            n,
+            p,
+            s: None,
+            crt: None,
+        }
     }
 
     /// Create private key variant
     pub fn private_key(n: BigUint, p: BigUint, s: BigUint) -> Self {
-        Self { n, p, s: Some(s) }
-    }
-
-    /// Generates a keypair
-    pub fn key_gen() -> Self {
-        let mut rng = ThreadRng::default();
-        let p = gen_prime(BigUint::from(100usize)..BigUint::from(1000usize), &mut rng);
-        let q = gen_prime(BigUint::from(100usize)..BigUint::from(1000usize), &mut rng);
-        let n = &p * &q;
-        let phi = (p - 1usize) * (q - 1usize);
-        let e = gen_rel_prime(BigUint::from(2usize)..&phi - 2usize, &phi, &mut rng);
-        let d = inv_mod(e.clone(), phi); //e^{-1} mod phi
         Self {
             n,
+            p,
+            s: Some(s),
+            crt: None,
+        }
+    }
+
+    /// Create a private key, with CRT parameters, from the two primes
+    ///
+    /// Returns `None` if e has no inverse mod lcm(p - 1, q - 1)
+    pub fn from_primes(p: BigUint, q: BigUint, e: BigUint) -> Option<Self> {
+        let (p1, q1) = (&p - 1u32, &q - 1u32);
+        let lambda = &p1 / gcd(p1.clone(), &q1) * &q1;
+        let d = inv_mod(e.clone(), lambda)?;
+        let crt = CrtParams {
+            dp: &d % &p1,
+            dq: &d % &q1,
+            qinv: inv_mod(q.clone(), p.clone())?,
+            p,
+            q,
+        };
+        Some(Self {
+            n: &crt.p * &crt.q,
             p: e,
             s: Some(d),
+            crt: Some(crt),
+        })
+    }
+
+    /// Generates a 2048 bit keypair, with e = 65537
+    pub fn key_gen() -> Self {
+        KeyGen::new(2048).generate()
+    }
+
+    pub fn modulus(&self) -> &BigUint {
+        &self.n
+    }
+
+    pub fn exponent(&self) -> &BigUint {
+        &self.p
+    }
+
+    /// The length of n in bytes, which every padded message has
+    pub fn size(&self) -> usize {
+        self.n.bits().div_ceil(8) as usize
+    }
+
+    pub fn crt_params(&self) -> Option<&CrtParams> {
+        self.crt.as_ref()
+    }
+
+    /// c^d mod n, using the CRT parameters if there are any
+    fn private_op(&self, c: BigUint) -> BigUint {
+        match &self.crt {
+            Some(crt) => {
+                let m1 = c.modpow(&crt.dp, &crt.p);
+                let m2 = c.modpow(&crt.dq, &crt.q);
+                // Garner's formula: m = m2 + q * (qInv * (m1 - m2) mod p)
+                let h = &crt.qinv * (m1 + &crt.p - &m2 % &crt.p) % &crt.p;
+                m2 + h * &crt.q
+            }
+            None => c.modpow(
+                self.s.as_ref().expect("Cannot decrypt w/o secret key"),
+                &self.n,
+            ),
         }
     }
 
@@ -49,10 +121,7 @@ impl RSAKey {
     /// Requires the private key
     /// Can be decrypted with `decrypt_sign`
     pub fn encrypt_sign(&self, m: BigUint) -> BigUint {
-        m.modpow(
-            self.s.as_ref().expect("Cannot decrypt w/o secret key"),
-            &self.n,
-        )
+        self.private_op(m)
     }
 
     /// Decrypts the provided integer
@@ -60,10 +129,7 @@ impl RSAKey {
     /// Requires the private key
     pub fn decrypt(&self, c: BigUint) -> BigUint {
         // c^s mod n
-        c.modpow(
-            self.s.as_ref().expect("Cannot decrypt w/o secret key"),
-            &self.n,
-        )
+        self.private_op(c)
     }
 
     /// Decrypts the provided integer as a signature
@@ -79,268 +145,155 @@ impl RSAKey {
             n: self.n.clone(),
             p: self.p.clone(),
             s: None,
+            crt: None,
         }
     }
 }
 
-fn gen_prime(size: Range<BigUint>, rng: &mut ThreadRng) -> BigUint {
-    let mut num = BigUint::from(0usize);
-    while !size.contains(&num) || !is_prime(num.clone()) {
-        println!("*");
-        num = rng.gen_biguint_range(&size.start, &size.end);
-    }
-    num
+/// How the primes p and q are picked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrimeKind {
+    /// Uniformly random primes of the right size
+    Random,
+    /// p = 2p' + 1 with p' prime, so p - 1 has no small factors but 2
+    Safe,
+    /// From Gordon's algorithm: p - 1 has a large prime factor r, p + 1 has a large prime factor,
+    /// and so does r - 1, which defeats Pollard's p - 1, Williams' p + 1 and cycling attacks
+    Strong,
 }
 
-fn gen_rel_prime(size: Range<BigUint>, to: &BigUint, rng: &mut ThreadRng) -> BigUint {
-    let mut num = BigUint::from(0usize);
-    while !size.contains(&num) || !is_prime_to(num.clone(), to) {
-        println!("+");
-        num = rng.gen_biguint_range(&size.start, &size.end);
-    }
-    num
+/// Builds an RSA key of a given size
+#[derive(Debug, Clone)]
+pub struct KeyGen {
+    bits: u64,
+    e: BigUint,
+    primes: PrimeKind,
 }
 
-fn is_prime(num: BigUint) -> bool {
-    // TODO: use a better prime test
-    let mut i = num.sqrt();
-    while i >= BigUint::from(2usize) {
-        if num.clone() % &i == BigUint::from(0usize) {
-            return false;
+impl KeyGen {
+    /// Keys with an n of exactly `bits` bits, which should be 1024 to 4096
+    pub fn new(bits: u64) -> Self {
+        Self {
+            bits,
+            e: BigUint::from(DEFAULT_EXPONENT),
+            primes: PrimeKind::Random,
         }
-        i -= 1usize;
     }
-    true
-}
 
-/// Checks if num is a perfect power
-///
-/// Also doesn't work
-fn perfect_power(num: &BigUint) -> bool {
-    /// All primes up to a limit - 2048
-    ///
-    /// Note that this limit is the log base 2 of the maximum size of integer for is_prime_aks
-    const EARLY_PRIMES: &[u64] = &[
-        2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89,
-        97, 101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181,
-        191, 193, 197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281,
-        283, 293, 307, 311, 313, 317, 331, 337, 347, 349, 353, 359, 367, 373, 379, 383, 389, 397,
-        401, 409, 419, 421, 431, 433, 439, 443, 449, 457, 461, 463, 467, 479, 487, 491, 499, 503,
-        509, 521, 523, 541, 547, 557, 563, 569, 571, 577, 587, 593, 599, 601, 607, 613, 617, 619,
-        631, 641, 643, 647, 653, 659, 661, 673, 677, 683, 691, 701, 709, 719, 727, 733, 739, 743,
-        751, 757, 761, 769, 773, 787, 797, 809, 811, 821, 823, 827, 829, 839, 853, 857, 859, 863,
-        877, 881, 883, 887, 907, 911, 919, 929, 937, 941, 947, 953, 967, 971, 977, 983, 991, 997,
-        1009, 1013, 1019, 1021, 1031, 1033, 1039, 1049, 1051, 1061, 1063, 1069, 1087, 1091, 1093,
-        1097, 1103, 1109, 1117, 1123, 1129, 1151, 1153, 1163, 1171, 1181, 1187, 1193, 1201, 1213,
-        1217, 1223, 1229, 1231, 1237, 1249, 1259, 1277, 1279, 1283, 1289, 1291, 1297, 1301, 1303,
-        1307, 1319, 1321, 1327, 1361, 1367, 1373, 1381, 1399, 1409, 1423, 1427, 1429, 1433, 1439,
-        1447, 1451, 1453, 1459, 1471, 1481, 1483, 1487, 1489, 1493, 1499, 1511, 1523, 1531, 1543,
-        1549, 1553, 1559, 1567, 1571, 1579, 1583, 1597, 1601, 1607, 1609, 1613, 1619, 1621, 1627,
-        1637, 1657, 1663, 1667, 1669, 1693, 1697, 1699, 1709, 1721, 1723, 1733, 1741, 1747, 1753,
-        1759, 1777, 1783, 1787, 1789, 1801, 1811, 1823, 1831, 1847, 1861, 1867, 1871, 1873, 1877,
-        1879, 1889, 1901, 1907, 1913, 1931, 1933, 1949, 1951, 1973, 1979, 1987, 1993, 1997, 1999,
-        2003, 2011, 2017, 2027, 2029, 2039,
-    ];
-
-    assert!(
-        num.bits() <= 2028,
-        "Only works on numbers smaller than 2^2048"
-    );
-    for prime in EARLY_PRIMES {
-        let prime = BigUint::from(*prime);
-        let mut power = prime.clone();
-        while &power < num {
-            power *= &prime;
-        }
-        if &power == num {
-            return true;
-        }
+    /// Uses a public exponent other than 65537, which has to be odd and at least 3
+    pub fn exponent(mut self, e: impl Into<BigUint>) -> Self {
+        self.e = e.into();
+        self
     }
-    false
-}
 
-#[derive(Debug, Clone)]
-struct BigUintRange {
-    cur: BigUint,
-    end: BigUint,
-}
-
-impl BigUintRange {
-    fn new(cur: BigUint, end: BigUint) -> Self {
-        Self { cur, end }
+    pub fn primes(mut self, primes: PrimeKind) -> Self {
+        self.primes = primes;
+        self
     }
-}
-
-impl UnindexedProducer for BigUintRange {
-    type Item = BigUint;
 
-    fn split(self) -> (Self, Option<Self>) {
-        let range = &self.end - &self.cur;
-        if range <= BigUint::from(10u8) {
-            (self, None)
-        } else {
-            let mid = range / 2u8 + &self.cur;
-            (
-                Self {
-                    cur: self.cur,
-                    end: &mid - 1u8,
-                },
-                Some(Self {
-                    cur: mid,
-                    end: self.end,
-                }),
-            )
+    /// A prime of `bits` bits with p - 1 coprime to e
+    fn prime(&self, bits: u64, rng: &mut ThreadRng) -> BigUint {
+        loop {
+            let p = match self.primes {
+                PrimeKind::Random => gen_prime(prime_range(bits), rng),
+                PrimeKind::Safe => gen_safe_prime(bits, rng),
+                PrimeKind::Strong => gen_strong_prime(bits, rng),
+            };
+            if is_prime_to(self.e.clone(), &(&p - 1u32)) {
+                return p;
+            }
         }
     }
 
-    fn fold_with<F>(self, folder: F) -> F
-    where
-        F: rayon::iter::plumbing::Folder<Self::Item>,
-    {
-        folder.consume_iter(self)
-    }
-}
-
-impl ParallelIterator for BigUintRange {
-    type Item = BigUint;
-    fn drive_unindexed<C>(self, consumer: C) -> C::Result
-    where
-        C: rayon::iter::plumbing::UnindexedConsumer<Self::Item>,
-    {
-        rayon::iter::plumbing::bridge_unindexed(self, consumer)
-    }
-}
-
-impl Iterator for BigUintRange {
-    type Item = BigUint;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.cur > self.end {
-            None
-        } else {
-            let ret = self.cur.clone();
-            self.cur += 1u8;
-            Some(ret)
+    pub fn generate(&self) -> RSAKey {
+        assert!(
+            self.e >= BigUint::from(3u32) && self.e.bit(0),
+            "e has to be odd and at least 3"
+        );
+        let mut rng = ThreadRng::default();
+        let p_bits = self.bits.div_ceil(2);
+        let p = self.prime(p_bits, &mut rng);
+        loop {
+            let q = self.prime(self.bits - p_bits, &mut rng);
+            // p and q that are too close together make n easy to factor with Fermat's method
+            let diff = if p > q { &p - &q } else { &q - &p };
+            if diff.bits() + 100 < p_bits {
+                continue;
+            }
+            if let Some(key) = RSAKey::from_primes(p.clone(), q, self.e.clone()) {
+                return key;
+            }
         }
     }
 }
 
-enum TestResult {
-    Unknown,
-    Prime,
-    Composite,
-}
-
-fn log2(n: &BigUint) -> u64 {
-    n.bits() - 1
+/// Numbers of exactly `bits` bits with the top two set, so the product of two has twice as many
+fn prime_range(bits: u64) -> Range<BigUint> {
+    let top = BigUint::from(1u32) << bits;
+    (&top >> 1u32) + (&top >> 2u32)..top
 }
 
-pub fn gcd(m: BigUint, n: &BigUint) -> BigUint {
-    if m == 0u64.into() {
-        n.clone()
-    } else {
-        gcd(n % &m, &m)
+fn gen_prime(size: Range<BigUint>, rng: &mut ThreadRng) -> BigUint {
+    let mut num = BigUint::from(0usize);
+    while !size.contains(&num) || !is_prime(&num) {
+        num = rng.gen_biguint_range(&size.start, &size.end);
     }
+    num
 }
 
-#[test]
-fn basic() {
-    use std::str::FromStr;
-    assert!(!is_prime_aks(&BigUint::from_str("4").unwrap()));
-    assert!(is_prime_aks(&BigUint::from_str("5").unwrap()));
-    assert!(is_prime_aks(&BigUint::from_str("7").unwrap()));
-    assert!(is_prime_aks(&BigUint::from_str("31").unwrap()));
-    assert!(is_prime_aks(&BigUint::from_str("101").unwrap()));
-    assert!(is_prime_aks(&BigUint::from_str("47939").unwrap()));
-    assert!(is_prime_aks(&BigUint::from_str("1652843").unwrap()));
-    assert!(!is_prime_aks(&BigUint::from_str("4218962626").unwrap()));
-    assert!(is_prime_aks(&BigUint::from_str("4218962623").unwrap()));
-    assert!(is_prime_aks(
-        &BigUint::from_str("288088873692602757671872933931").unwrap()
-    ));
-    let p = BigUint::from_str(
-        "57896044618658097711785492504343953926634992332820282019728792003956564819949",
-    )
-    .unwrap();
-    assert!(is_prime_aks(&p));
-}
-
-/// Checks whether the given number is prime, using the AKS test
-pub fn is_prime_aks(n: &BigUint) -> bool {
-    if n <= &BigUint::from(1u8) {
-        return false;
-    }
-    println!("In test 1: {}", n);
-    let top_limit = log2(n);
-
-    let found_any_integer = (2..=top_limit).into_par_iter().any(|b| {
-        let rounded = n.nth_root(b as u32);
-        &rounded.pow(b as u32) == n
-    });
-    if !found_any_integer {
-        test2(n)
-    } else {
-        false
+/// p = 2q + 1 with both prime, and p of `bits` bits
+///
+/// Candidates where either has a small factor are thrown out before the expensive tests, since
+/// almost all of them do.
+fn gen_safe_prime(bits: u64, rng: &mut ThreadRng) -> BigUint {
+    let range = prime_range(bits);
+    let (low, high) = (&range.start >> 1u32, &range.end >> 1u32);
+    loop {
+        let q = rng.gen_biguint_range(&low, &high);
+        let p = &q * 2u32 + 1u32;
+        // Both are far bigger than any of the small primes, so can't be one of them
+        let small_factor = SMALL_PRIMES
+            .iter()
+            .any(|&r| (&q % r).bits() == 0 || (&p % r).bits() == 0);
+        if !small_factor && is_prime(&p) && is_prime(&q) {
+            return p;
+        }
     }
 }
 
-/// Calculate r, smallest r st. Or(n) > log2(n)^2
-fn test2(n: &BigUint) -> bool {
-    println!("In test 2");
-    let maxk = BigUint::from(log2(n)).pow(2);
-    let maxr = (BigUint::from(log2(n)).pow(5) + 1u8).max(BigUint::from(3u8));
-
-    let krange = BigUintRange::new(BigUint::from(1u8), maxk);
-
-    let final_r = BigUintRange::new(BigUint::from(2u8), &maxr - 1u8)
-        .find_any(|r| !krange.clone().any(|k| n.modpow(&k, r) < BigUint::from(1u8)))
-        .unwrap_or(maxr);
-    test3(n, final_r)
-}
-
-/// check if 1 < gcd(a, n) < n for some a <= r => composite
-fn test3(n: &BigUint, r: BigUint) -> bool {
-    println!("In test 3");
-    let found_any =
-        BigUintRange::new(BigUint::from(1u8), r.clone()).any(|a| gcd(a, n) > BigUint::from(1u8));
-    if found_any {
-        false
-    } else {
-        if n <= &r {
-            true
-        } else {
-            test5(n)
+/// Gordon's algorithm
+///
+/// With random primes s and t of about half the size, r is the first prime 2it + 1, and
+/// p0 = 2 (s^(r - 2) mod r) s - 1, so that p0 = 1 mod r and -1 mod s. Then p is the first prime
+/// p0 + 2jrs in range, which keeps both.
+fn gen_strong_prime(bits: u64, rng: &mut ThreadRng) -> BigUint {
+    let half = (bits / 2).saturating_sub(16).max(8);
+    let range = prime_range(bits);
+    loop {
+        let s = gen_prime(prime_range(half), rng);
+        let t = gen_prime(prime_range(half - 4), rng);
+        let mut r = &t * 2u32 + 1u32;
+        while !is_prime(&r) {
+            r += &t * 2u32;
+        }
+        let p0 = (s.modpow(&(&r - 2u32), &r) * &s * 2u32) - 1u32;
+        let step = &r * &s * 2u32;
+        // The first p = p0 mod 2rs in range
+        let mut p = &range.start + (&p0 % &step + &step - &range.start % &step) % &step;
+        while p < range.end {
+            if is_prime(&p) {
+                return p;
+            }
+            p += &step;
         }
     }
 }
 
-///
-fn test5(n: &BigUint) -> bool {
-    println!("In test 5: max {}", n / 2u8 - 1u8);
-    let mut current_root = BigUint::from(1u8);
-    let mut iter = BigUintRange::new(1u8.into(), n / 2u8 - 1u8);
-    let has_divisible_coeffient = Iterator::any(&mut iter, |a| {
-        //a.modpow(n, n) - a != BigUint::from(0u8)
-        current_root *= n - &a + 1u8;
-        current_root /= a;
-        if &current_root % n != 0u8.into() {
-            true
-        } else {
-            false
-        }
-    });
-    //let has_divisible_coeffient = BigUintRange::new(1u8.into(), n / 2u8 - 1u8).any(|a| {
-    //current_root *= n - &a + 1u8;
-    //current_root /= a;
-    //if &current_root % n != 0u8.into() {
-    //true
-    //} else {
-    //false
-    //}
-    //});
-    if has_divisible_coeffient {
-        false
+pub fn gcd(m: BigUint, n: &BigUint) -> BigUint {
+    if m == 0u64.into() {
+        n.clone()
     } else {
-        true
+        gcd(n % &m, &m)
     }
 }
 
@@ -358,16 +311,14 @@ fn is_prime_to(num: BigUint, to: &BigUint) -> bool {
     max == BigUint::from(1usize)
 }
 
-fn inv_mod(num: BigUint, modulus: BigUint) -> BigUint {
-    // TODO: use faster algo
-    let mut i = BigUint::from(0usize);
-    while i < modulus {
-        if (i.clone() * num.clone()) % modulus.clone() == BigUint::from(1usize) {
-            return i;
-        }
-        i += 1usize;
+/// num^-1 mod modulus, if they're coprime
+fn inv_mod(num: BigUint, modulus: BigUint) -> Option<BigUint> {
+    let modulus = BigInt::from(modulus);
+    let (x, _, g) = extended_euclid(num, modulus.clone());
+    if g != BigInt::from(1u32) {
+        return None;
     }
-    panic!("No inverse found")
+    ((x % &modulus + &modulus) % &modulus).to_biguint()
 }
 
 #[cfg(test)]
@@ -409,3 +360,64 @@ mod fermat {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_key(key: &RSAKey, bits: u64) {
+        let crt = key.crt_params().unwrap();
+        let d = key.s.as_ref().unwrap();
+        assert_eq!(key.n.bits(), bits);
+        assert_eq!(&crt.p * &crt.q, key.n);
+        assert_eq!(crt.dp, d % (&crt.p - 1u32));
+        assert_eq!(crt.dq, d % (&crt.q - 1u32));
+        assert_eq!(&crt.qinv * &crt.q % &crt.p, BigUint::from(1u32));
+
+        let m = rand::thread_rng().gen_biguint_below(&key.n);
+        let c = key.encrypt(m.clone());
+        assert_eq!(key.decrypt(c.clone()), m);
+        // The CRT gives the same answer as c^d mod n
+        assert_eq!(key.decrypt(c.clone()), c.modpow(d, &key.n));
+        assert_eq!(key.decrypt_sign(key.encrypt_sign(m.clone())), m);
+        assert_eq!(key.public_key_part().encrypt(m.clone()), c);
+    }
+
+    #[test]
+    fn textbook() {
+        let key = RSAKey::from_primes(61u32.into(), 53u32.into(), 17u32.into()).unwrap();
+        assert_eq!(key.n, BigUint::from(3233u32));
+        // d is the inverse mod lcm(60, 52) = 780
+        assert_eq!(key.s, Some(BigUint::from(413u32)));
+        assert_eq!(key.encrypt(65u32.into()), BigUint::from(2790u32));
+        assert_eq!(key.decrypt(2790u32.into()), BigUint::from(65u32));
+        // e = 3 isn't invertible mod 780
+        assert!(RSAKey::from_primes(61u32.into(), 53u32.into(), 3u32.into()).is_none());
+        assert_eq!(inv_mod(6u32.into(), 9u32.into()), None);
+    }
+
+    #[test]
+    fn key_sizes() {
+        let key = KeyGen::new(1024).generate();
+        assert_eq!(key.p, BigUint::from(DEFAULT_EXPONENT));
+        check_key(&key, 1024);
+        check_key(&KeyGen::new(513).exponent(3u32).generate(), 513);
+    }
+
+    #[test]
+    fn safe_primes() {
+        let key = KeyGen::new(256).primes(PrimeKind::Safe).generate();
+        check_key(&key, 256);
+        let crt = key.crt_params().unwrap();
+        assert!(is_prime(&((&crt.p - 1u32) / 2u32)));
+        assert!(is_prime(&((&crt.q - 1u32) / 2u32)));
+    }
+
+    #[test]
+    fn strong_primes() {
+        check_key(&KeyGen::new(512).primes(PrimeKind::Strong).generate(), 512);
+        let p = gen_strong_prime(256, &mut ThreadRng::default());
+        assert!(prime_range(256).contains(&p));
+        assert!(is_prime(&p));
+    }
+}
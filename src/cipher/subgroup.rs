@@ -0,0 +1,229 @@
+//
+// subgroup.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Small subgroup confinement, from challenge 57
+//!
+//! When g generates a subgroup of prime order q, but p - 1 = q * j has small factors r, there are
+//! elements h of order r. A victim that uses its private key x with h, without checking h is in
+//! the subgroup, computes K = h^x, which only takes r values. MACing a message with K lets the
+//! attacker find x mod r by trying them all. Enough of these residues recombine with the CRT into
+//! x mod q, which is x itself.
+
+use lazy_static::lazy_static;
+use num_bigint::{BigInt, BigUint, RandBigInt};
+
+use super::diffie::{DhError, DhGroup};
+use super::util::crt;
+use crate::data::Bytes;
+use crate::hash::{hmac::Hmac, sha2::Sha256};
+
+lazy_static! {
+    /// The group from challenge 57, where (p - 1) / q has many factors below 2^16
+    pub static ref CH57: DhGroup = DhGroup::new(
+        BigUint::parse_bytes(
+            b"7199773997391911030609999317773941274322764333428698921736339643928346453700085358\
+              802973900485592910475480089726140708102474957429903531369589969318716771",
+            10
+        )
+        .unwrap(),
+        BigUint::parse_bytes(
+            b"4565356397095740655436854503483826832136106141639563487732438195343690437606117828\
+              318042418238184896212352329118608100083187535033402010599512641674644143",
+            10
+        )
+        .unwrap(),
+    )
+    .order(BigUint::parse_bytes(b"236234353446506858198510045061214171961", 10).unwrap());
+}
+
+/// The distinct prime factors of `n` below `bound`
+pub fn small_factors(n: &BigUint, bound: u32) -> Vec<u32> {
+    let mut n = n.clone();
+    let mut factors = vec![];
+    for d in 2..bound {
+        // Smaller primes have already been divided out, so any d that divides n is prime
+        if (&n % d).bits() == 0 {
+            factors.push(d);
+            while (&n % d).bits() == 0 {
+                n /= d;
+            }
+        }
+    }
+    factors
+}
+
+/// A random element of order `r`, which has to be a prime factor of p - 1
+pub fn element_of_order(p: &BigUint, r: u32) -> BigUint {
+    let one = BigUint::from(1u32);
+    let exp = (p - 1u32) / r;
+    loop {
+        let h = rand::thread_rng()
+            .gen_biguint_range(&BigUint::from(2u32), p)
+            .modpow(&exp, p);
+        if h != one {
+            return h;
+        }
+    }
+}
+
+/// The MAC key for a shared secret, which is just its big endian bytes
+pub fn mac_key(k: &BigUint) -> Bytes {
+    Bytes::from_vec(k.to_bytes_be())
+}
+
+/// Bob, who MACs a message with whatever public key he's sent
+pub struct Victim {
+    group: DhGroup,
+    private: BigUint,
+    message: Bytes,
+    check_public_key: bool,
+}
+
+impl Victim {
+    pub fn new(group: DhGroup) -> Self {
+        let private = group.private_key();
+        Self {
+            group,
+            private,
+            message: Bytes::read_utf8("crazy flamboyant for the rap enjoyment"),
+            check_public_key: false,
+        }
+    }
+
+    /// Validates public keys with `DhGroup::validate`, which includes the subgroup check
+    pub fn checked(mut self) -> Self {
+        self.check_public_key = true;
+        self
+    }
+
+    /// Only used to check the attack
+    pub fn private_key(&self) -> &BigUint {
+        &self.private
+    }
+
+    /// Returns the message and its HMAC-SHA256 under the key derived from h^x
+    pub fn respond(&self, h: &BigUint) -> Result<(Bytes, Bytes), DhError> {
+        if self.check_public_key {
+            self.group.validate(h)?;
+        }
+        let k = h.modpow(&self.private, &self.group.p);
+        let tag = Hmac::<Sha256>::mac(&mac_key(&k), &self.message);
+        Ok((self.message.clone(), tag))
+    }
+}
+
+/// Finds k < r such that `tag` is the MAC of `message` under h^k
+pub fn brute_force_residue(
+    p: &BigUint,
+    h: &BigUint,
+    r: u32,
+    message: &Bytes,
+    tag: &Bytes,
+) -> Option<u32> {
+    let mut k = BigUint::from(1u32);
+    for candidate in 0..r {
+        if Hmac::<Sha256>::mac(&mac_key(&k), message).ct_eq(tag) {
+            return Some(candidate);
+        }
+        k = k * h % p;
+    }
+    None
+}
+
+/// Finds the private key mod as many small factors of (p - 1) / q as it takes to pass q
+///
+/// `oracle` is sent elements of small order, and returns a message and MAC, or `None` if the
+/// victim refused. Returns (x mod m, m), where m is the product of the factors used. m can be
+/// less than q if there weren't enough factors below `bound`.
+pub fn partial_key(
+    group: &DhGroup,
+    bound: u32,
+    mut oracle: impl FnMut(&BigUint) -> Option<(Bytes, Bytes)>,
+) -> Option<(BigUint, BigUint)> {
+    let q = group.q.as_ref()?;
+    let j = (&group.p - 1u32) / q;
+    let (mut residues, mut moduli) = (vec![], vec![]);
+    let mut product = BigUint::from(1u32);
+    for r in small_factors(&j, bound) {
+        // A factor shared with q would give an element inside the subgroup
+        if (q % r).bits() == 0 {
+            continue;
+        }
+        let h = element_of_order(&group.p, r);
+        let (message, tag) = oracle(&h)?;
+        let k = brute_force_residue(&group.p, &h, r, &message, &tag)?;
+        residues.push(BigInt::from(k));
+        moduli.push(BigInt::from(r));
+        product *= r;
+        if product > *q {
+            break;
+        }
+    }
+    if moduli.is_empty() {
+        return Some((BigUint::from(0u32), product));
+    }
+    let (x, m) = crt(&residues, &moduli);
+    let x = ((x % &m) + &m) % &m;
+    Some((x.to_biguint().unwrap(), m.to_biguint().unwrap()))
+}
+
+/// Recovers the whole private key, if (p - 1) / q has enough small factors
+pub fn recover_private_key(
+    group: &DhGroup,
+    bound: u32,
+    oracle: impl FnMut(&BigUint) -> Option<(Bytes, Bytes)>,
+) -> Option<BigUint> {
+    let (x, m) = partial_key(group, bound, oracle)?;
+    if m > *group.q.as_ref()? {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn factors() {
+        let j = (&CH57.p - 1u32) / CH57.q.as_ref().unwrap();
+        assert_eq!(
+            small_factors(&j, 1 << 16),
+            vec![2, 3, 5, 109, 7963, 8539, 20641, 38833, 39341, 46337, 51977, 54319, 57529]
+        );
+        assert_eq!(small_factors(&BigUint::from(360u32), 100), vec![2, 3, 5]);
+        for r in [2, 5, 109] {
+            let h = element_of_order(&CH57.p, r);
+            assert_eq!(h.modpow(&BigUint::from(r), &CH57.p), BigUint::from(1u32));
+        }
+    }
+
+    #[test]
+    fn residue() {
+        let p = &CH57.p;
+        let h = element_of_order(p, 109);
+        let message = Bytes::read_utf8("test");
+        let k = h.modpow(&BigUint::from(57u32), p);
+        let tag = Hmac::<Sha256>::mac(&mac_key(&k), &message);
+        assert_eq!(brute_force_residue(p, &h, 109, &message, &tag), Some(57));
+    }
+
+    #[test]
+    fn challenge_57() {
+        let victim = Victim::new(CH57.clone());
+        let x = recover_private_key(&CH57, 1 << 16, |h| victim.respond(h).ok());
+        assert_eq!(x.as_ref(), Some(victim.private_key()));
+
+        let victim = Victim::new(CH57.clone()).checked();
+        assert_eq!(
+            recover_private_key(&CH57, 1 << 16, |h| victim.respond(h).ok()),
+            None
+        );
+        let h = element_of_order(&CH57.p, 5);
+        assert_eq!(victim.respond(&h), Err(DhError::NotInSubgroup));
+    }
+}
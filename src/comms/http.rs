@@ -0,0 +1,264 @@
+//
+// http.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Local stand-in for the web app from challenges 31 and 32
+//!
+//! Serves `GET /test?file=<file>&signature=<hex>` on loopback, and responds with 200 if
+//! `HMAC::weak_verify` accepts the signature, or 500 otherwise. Since `weak_verify` exits on the
+//! first wrong byte, the time to get a response leaks how much of the signature is correct.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::data::Bytes;
+use crate::mac::HMAC;
+use crate::timing::{ByteGuess, TimingAttack};
+
+/// HTTP server on a random loopback port, which stops when dropped
+pub struct HmacServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl HmacServer {
+    /// Starts the server on a background thread
+    ///
+    /// Requests are handled one at a time, so concurrent requests don't disturb each other's
+    /// timing
+    pub fn start(hmac: HMAC) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    // A client hanging up early isn't a problem for the server
+                    let _ = handle_request(stream, &hmac);
+                }
+            }
+        });
+        Ok(Self {
+            addr,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for HmacServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake up the listener, so it sees the stop flag
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn handle_request(stream: TcpStream, hmac: &HMAC) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let status = match parse_request(&request_line) {
+        Some((file, signature)) => {
+            if hmac.weak_verify(file.as_bytes(), &signature) {
+                "200 OK"
+            } else {
+                "500 Internal Server Error"
+            }
+        }
+        None => "400 Bad Request",
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )?;
+    stream.flush()
+}
+
+/// Gets `file` and `signature` from `GET /test?file=..&signature=.. HTTP/1.1`
+fn parse_request(request_line: &str) -> Option<(String, Bytes)> {
+    let mut parts = request_line.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+    let (path, query) = parts.next()?.split_once('?')?;
+    if path != "/test" {
+        return None;
+    }
+    let mut file = None;
+    let mut signature = None;
+    for pair in query.split('&') {
+        match pair.split_once('=')? {
+            ("file", value) => file = Some(url_decode(value)?),
+            ("signature", value) => {
+                if value.len() % 2 != 0 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return None;
+                }
+                signature = Some(Bytes::read_hex(value));
+            }
+            _ => (),
+        }
+    }
+    Some((file?, signature?))
+}
+
+fn url_decode(s: &str) -> Option<String> {
+    let mut ret = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                ret.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b'+' => ret.push(b' '),
+            b => ret.push(b),
+        }
+    }
+    String::from_utf8(ret).ok()
+}
+
+fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Makes a single request to the server
+///
+/// Returns whether the signature was accepted, and how long the server took to respond
+pub fn request(addr: SocketAddr, file: &str, signature: &Bytes) -> io::Result<(bool, Duration)> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    let request = format!(
+        "GET /test?file={}&signature={} HTTP/1.1\r\nHost: {}\r\n\r\n",
+        url_encode(file),
+        signature.to_lower_hex(),
+        addr
+    );
+    let start = Instant::now();
+    stream.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let elapsed = start.elapsed();
+    Ok((response.starts_with("HTTP/1.1 200"), elapsed))
+}
+
+/// Recovers a signature from an `HmacServer`, by timing requests
+pub struct TimingAttacker {
+    addr: SocketAddr,
+    attack: TimingAttack,
+}
+
+impl TimingAttacker {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            attack: TimingAttack::new(),
+        }
+    }
+
+    /// Number of requests to time for each candidate byte, per round
+    pub fn samples(mut self, samples: usize) -> Self {
+        self.attack = self.attack.samples(samples);
+        self
+    }
+
+    /// Replaces the default attack parameters
+    pub fn attack(mut self, attack: TimingAttack) -> Self {
+        self.attack = attack;
+        self
+    }
+
+    /// A failed request counts as a rejected signature
+    fn verify<'a>(&'a self, file: &'a str) -> impl Fn(&Bytes) -> bool + 'a {
+        move |signature| {
+            request(self.addr, file, signature)
+                .map(|(valid, _)| valid)
+                .unwrap_or(false)
+        }
+    }
+
+    /// Finds the byte after `known`, for a signature of `len` bytes
+    pub fn recover_byte(&self, file: &str, known: &Bytes, len: usize) -> ByteGuess {
+        self.attack.recover_byte(self.verify(file), known, len)
+    }
+
+    /// Recovers the full `len` byte signature for `file`
+    pub fn recover(&self, file: &str, len: usize) -> Option<Bytes> {
+        self.attack.recover(self.verify(file), len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_request("GET /test?file=foo%20bar&signature=00ff HTTP/1.1\r\n"),
+            Some(("foo bar".to_string(), Bytes::from_bytes(&[0x00, 0xff])))
+        );
+        assert_eq!(parse_request("GET /test?file=foo HTTP/1.1\r\n"), None);
+        assert_eq!(
+            parse_request("GET /test?file=foo&signature=zz HTTP/1.1\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn server() {
+        let hmac = HMAC::init(0);
+        let signature = hmac.sign(b"some file");
+        let server = HmacServer::start(hmac).unwrap();
+        assert!(request(server.addr(), "some file", &signature).unwrap().0);
+        assert!(!request(server.addr(), "other file", &signature).unwrap().0);
+    }
+
+    // Depends on loopback latency and the scheduler, so this is run by hand with
+    // `cargo test --release -- --ignored leaks_first_bytes`
+    #[test]
+    #[ignore = "timing measurement, run by hand in release mode"]
+    fn leaks_first_bytes() {
+        let hmac = HMAC::init(5);
+        let signature = hmac.sign(b"test");
+        let server = HmacServer::start(hmac).unwrap();
+        let attacker = TimingAttacker::new(server.addr()).samples(3);
+        let first = attacker.recover_byte("test", &Bytes::new(), signature.len());
+        assert_eq!(first.byte, signature[0]);
+        let second = attacker.recover_byte("test", &signature.truncate(1), signature.len());
+        assert_eq!(second.byte, signature[1]);
+    }
+}
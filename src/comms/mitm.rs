@@ -0,0 +1,478 @@
+//
+// mitm.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! A programmable man in the middle, for `comm_channel_mitm`
+//!
+//! Each direction has its own list of hooks, which every intercepted message is passed through in
+//! order. A hook can look at the message, replace it, drop it by returning `None`, or send extra
+//! messages either way through the `Proxy` it's given. Everything intercepted and delivered is
+//! recorded in a transcript, which is returned once both sides hang up.
+//!
+//! ```ignore
+//! let transcript = Mitm::new()
+//!     .observe(Direction::AToB, |m| println!("A said {:?}", m))
+//!     .rewrite(Direction::BToA, |m| tamper(m))
+//!     .run(a, b);
+//! ```
+
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use num_bigint::{BigUint, RandBigInt};
+
+use super::comm_channel_mitm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    AToB,
+    BToA,
+}
+
+/// One message passing through the proxy
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry<T> {
+    pub direction: Direction,
+    /// What the sender sent, or `None` if the proxy injected the message
+    pub intercepted: Option<T>,
+    /// What the receiver got, or `None` if the message was dropped
+    pub delivered: Option<T>,
+}
+
+type Link<T> = Arc<Mutex<Option<Sender<T>>>>;
+
+/// What a hook can do besides changing the message it was given
+pub struct Proxy<T> {
+    to_a: Link<T>,
+    to_b: Link<T>,
+    transcript: Arc<Mutex<Vec<Entry<T>>>>,
+}
+
+impl<T> Clone for Proxy<T> {
+    fn clone(&self) -> Self {
+        Self {
+            to_a: self.to_a.clone(),
+            to_b: self.to_b.clone(),
+            transcript: self.transcript.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Proxy<T> {
+    fn link(&self, direction: Direction) -> &Link<T> {
+        match direction {
+            Direction::AToB => &self.to_b,
+            Direction::BToA => &self.to_a,
+        }
+    }
+
+    fn send(&self, direction: Direction, message: T) -> bool {
+        match &*self.link(direction).lock().unwrap() {
+            Some(tx) => tx.send(message).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Sends a message that neither side sent
+    ///
+    /// Fails silently if the receiver has hung up, or the sender has, since the receiver is told
+    /// about that by closing its channel.
+    pub fn inject(&self, direction: Direction, message: T) {
+        if self.send(direction, message.clone()) {
+            self.transcript.lock().unwrap().push(Entry {
+                direction,
+                intercepted: None,
+                delivered: Some(message),
+            });
+        }
+    }
+
+    /// Sends the `index`th intercepted message again, in the direction it was first sent
+    ///
+    /// Returns false if there is no such message
+    pub fn replay(&self, index: usize) -> bool {
+        let entry = self
+            .transcript
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|e| Some((e.direction, e.intercepted.clone()?)))
+            .nth(index);
+        match entry {
+            Some((direction, message)) => {
+                self.inject(direction, message);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Everything that has passed through the proxy so far
+    pub fn transcript(&self) -> Vec<Entry<T>> {
+        self.transcript.lock().unwrap().clone()
+    }
+}
+
+type Hook<T> = Box<dyn FnMut(T, &Proxy<T>) -> Option<T> + Send>;
+
+pub struct Mitm<T> {
+    a_to_b: Vec<Hook<T>>,
+    b_to_a: Vec<Hook<T>>,
+}
+
+impl<T: Clone + Send + 'static> Default for Mitm<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone + Send + 'static> Mitm<T> {
+    /// A proxy that forwards everything unchanged
+    pub fn new() -> Self {
+        Self {
+            a_to_b: vec![],
+            b_to_a: vec![],
+        }
+    }
+
+    /// Adds a hook for messages going `direction`, run after the ones already added
+    pub fn hook(
+        mut self,
+        direction: Direction,
+        hook: impl FnMut(T, &Proxy<T>) -> Option<T> + Send + 'static,
+    ) -> Self {
+        match direction {
+            Direction::AToB => self.a_to_b.push(Box::new(hook)),
+            Direction::BToA => self.b_to_a.push(Box::new(hook)),
+        }
+        self
+    }
+
+    pub fn observe(self, direction: Direction, mut f: impl FnMut(&T) + Send + 'static) -> Self {
+        self.hook(direction, move |message, _| {
+            f(&message);
+            Some(message)
+        })
+    }
+
+    pub fn rewrite(self, direction: Direction, mut f: impl FnMut(T) -> T + Send + 'static) -> Self {
+        self.hook(direction, move |message, _| Some(f(message)))
+    }
+
+    /// Drops messages that `f` returns true for
+    pub fn drop_if(
+        self,
+        direction: Direction,
+        mut f: impl FnMut(&T) -> bool + Send + 'static,
+    ) -> Self {
+        self.hook(
+            direction,
+            move |message, _| {
+                if f(&message) {
+                    None
+                } else {
+                    Some(message)
+                }
+            },
+        )
+    }
+
+    /// Sends `message` ahead of the `nth` message going `direction`, counting from 0
+    pub fn inject(self, direction: Direction, nth: usize, message: T) -> Self {
+        let mut count = 0;
+        let mut message = Some(message);
+        self.hook(direction, move |intercepted, proxy| {
+            if count == nth {
+                proxy.inject(direction, message.take().unwrap());
+            }
+            count += 1;
+            Some(intercepted)
+        })
+    }
+
+    /// Sends every message going `direction` twice
+    pub fn replay(self, direction: Direction) -> Self {
+        self.hook(direction, move |message, proxy| {
+            proxy.inject(direction, message.clone());
+            Some(message)
+        })
+    }
+
+    /// Relays between the two sides until both have hung up, returning the transcript
+    ///
+    /// The arguments are the ones `comm_channel_mitm` hands to the attacker
+    pub fn intercept(
+        self,
+        to_a: Sender<T>,
+        from_a: Receiver<T>,
+        to_b: Sender<T>,
+        from_b: Receiver<T>,
+    ) -> Vec<Entry<T>> {
+        let proxy = Proxy {
+            to_a: Arc::new(Mutex::new(Some(to_a))),
+            to_b: Arc::new(Mutex::new(Some(to_b))),
+            transcript: Arc::new(Mutex::new(vec![])),
+        };
+        let a_to_b = relay(Direction::AToB, self.a_to_b, from_a, proxy.clone());
+        let b_to_a = relay(Direction::BToA, self.b_to_a, from_b, proxy.clone());
+        a_to_b.join().unwrap();
+        b_to_a.join().unwrap();
+        let transcript = proxy.transcript.lock().unwrap().clone();
+        transcript
+    }
+
+    /// Runs `a` and `b` through this proxy with `comm_channel_mitm`, returning the transcript
+    pub fn run(
+        self,
+        a: impl Fn(Sender<T>, Receiver<T>) + Send + 'static,
+        b: impl Fn(Sender<T>, Receiver<T>) + Send + 'static,
+    ) -> Vec<Entry<T>> {
+        let mitm = Mutex::new(Some(self));
+        let transcript = Arc::new(Mutex::new(vec![]));
+        let result = transcript.clone();
+        comm_channel_mitm(a, b, move |to_a, from_a, to_b, from_b| {
+            let mitm = mitm.lock().unwrap().take().unwrap();
+            *result.lock().unwrap() = mitm.intercept(to_a, from_a, to_b, from_b);
+        });
+        let transcript = transcript.lock().unwrap().clone();
+        transcript
+    }
+}
+
+fn relay<T: Clone + Send + 'static>(
+    direction: Direction,
+    mut hooks: Vec<Hook<T>>,
+    rx: Receiver<T>,
+    proxy: Proxy<T>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        while let Ok(intercepted) = rx.recv() {
+            let delivered = hooks
+                .iter_mut()
+                .try_fold(intercepted.clone(), |message, hook| hook(message, &proxy));
+            // Recorded before sending, so the receiver can't reply before this is in the transcript
+            proxy.transcript.lock().unwrap().push(Entry {
+                direction,
+                intercepted: Some(intercepted),
+                delivered: delivered.clone(),
+            });
+            if let Some(message) = delivered {
+                if !proxy.send(direction, message) {
+                    break;
+                }
+            }
+        }
+        // Pass on the hang up, so the receiver doesn't wait forever
+        proxy.link(direction).lock().unwrap().take();
+    })
+}
+
+/// A message that may carry a Diffie-Hellman public key, for the scripted scenarios
+pub trait PublicKey {
+    fn public_key(&self) -> Option<&BigUint>;
+
+    /// The same message carrying `key` instead. Only called when `public_key` is `Some`
+    fn with_public_key(self, key: BigUint) -> Self;
+}
+
+/// Replaces every public key with `p`, so both sides compute a shared secret of p^x mod p = 0
+pub fn parameter_injection<T: PublicKey + Clone + Send + 'static>(p: BigUint) -> Mitm<T> {
+    let inject = move |message: T| match message.public_key() {
+        Some(_) => message.with_public_key(p.clone()),
+        None => message,
+    };
+    Mitm::new()
+        .rewrite(Direction::AToB, inject.clone())
+        .rewrite(Direction::BToA, inject)
+}
+
+/// The secrets the proxy shares with each side after substituting its own key
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Secrets {
+    pub with_a: Option<BigUint>,
+    pub with_b: Option<BigUint>,
+}
+
+/// Replaces each side's public key with the proxy's own g^m mod p
+///
+/// A and B each end up sharing a secret with the proxy instead of each other. The secrets are
+/// filled in as the keys go past, so hooks added afterwards can use them to decrypt and
+/// re-encrypt traffic.
+pub fn key_substitution<T: PublicKey + Clone + Send + 'static>(
+    p: BigUint,
+    g: BigUint,
+) -> (Mitm<T>, Arc<Mutex<Secrets>>) {
+    let m = rand::thread_rng().gen_biguint_below(&p);
+    let m_pub = g.modpow(&m, &p);
+    let secrets = Arc::new(Mutex::new(Secrets::default()));
+    let substitute = |direction| {
+        let (p, m, m_pub, secrets) = (p.clone(), m.clone(), m_pub.clone(), secrets.clone());
+        move |message: T| {
+            let secret = match message.public_key() {
+                Some(key) => key.modpow(&m, &p),
+                None => return message,
+            };
+            let mut secrets = secrets.lock().unwrap();
+            match direction {
+                Direction::AToB => secrets.with_a = Some(secret),
+                Direction::BToA => secrets.with_b = Some(secret),
+            }
+            message.with_public_key(m_pub.clone())
+        }
+    };
+    let mitm = Mitm::new()
+        .rewrite(Direction::AToB, substitute(Direction::AToB))
+        .rewrite(Direction::BToA, substitute(Direction::BToA));
+    (mitm, secrets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_a(tx: Sender<u32>, rx: Receiver<u32>) {
+        for i in 0..3 {
+            tx.send(i).unwrap();
+            rx.recv().unwrap();
+        }
+    }
+
+    fn echo_b(tx: Sender<u32>, rx: Receiver<u32>) {
+        for message in rx.iter() {
+            if tx.send(message * 10).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn delivered(transcript: &[Entry<u32>], direction: Direction) -> Vec<u32> {
+        transcript
+            .iter()
+            .filter(|e| e.direction == direction)
+            .filter_map(|e| e.delivered)
+            .collect()
+    }
+
+    #[test]
+    fn forward() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let observed = seen.clone();
+        let transcript = Mitm::new()
+            .observe(Direction::BToA, move |m| observed.lock().unwrap().push(*m))
+            .run(echo_a, echo_b);
+        assert_eq!(*seen.lock().unwrap(), vec![0, 10, 20]);
+        assert_eq!(transcript.len(), 6);
+        assert!(transcript.iter().all(|e| e.intercepted == e.delivered));
+    }
+
+    #[test]
+    fn rewrite_and_drop() {
+        let transcript = Mitm::new()
+            .rewrite(Direction::AToB, |m| m + 1)
+            // Hooks after one that drops a message don't see it, so this has to come first
+            .inject(Direction::BToA, 2, 99)
+            .drop_if(Direction::BToA, |&m| m == 30)
+            .run(echo_a, echo_b);
+        assert_eq!(delivered(&transcript, Direction::AToB), vec![1, 2, 3]);
+        assert_eq!(delivered(&transcript, Direction::BToA), vec![10, 20, 99]);
+        let dropped = transcript.iter().find(|e| e.delivered.is_none()).unwrap();
+        assert_eq!(dropped.intercepted, Some(30));
+    }
+
+    #[test]
+    fn replay() {
+        let transcript = Mitm::new()
+            .hook(Direction::AToB, |m, proxy| {
+                if m == 2 {
+                    assert!(proxy.replay(0));
+                    assert!(!proxy.replay(100));
+                }
+                Some(m)
+            })
+            .run(
+                |tx, _| (0..3).for_each(|i| tx.send(i).unwrap()),
+                |tx, rx| {
+                    let got: Vec<u32> = rx.iter().take(4).collect();
+                    assert_eq!(got, vec![0, 1, 0, 2]);
+                    drop(tx);
+                },
+            );
+        assert_eq!(
+            transcript
+                .iter()
+                .filter(|e| e.intercepted.is_none())
+                .count(),
+            1
+        );
+
+        let transcript = Mitm::new().replay(Direction::AToB).run(
+            |tx, _| tx.send(5).unwrap(),
+            |_, rx| assert_eq!(rx.iter().count(), 2),
+        );
+        assert_eq!(delivered(&transcript, Direction::AToB), vec![5, 5]);
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Dh {
+        Key(BigUint),
+        Data(u32),
+    }
+
+    impl PublicKey for Dh {
+        fn public_key(&self) -> Option<&BigUint> {
+            match self {
+                Dh::Key(key) => Some(key),
+                _ => None,
+            }
+        }
+
+        fn with_public_key(self, key: BigUint) -> Self {
+            Dh::Key(key)
+        }
+    }
+
+    /// Exchanges keys, then sends the shared secret both ways
+    fn dh_party(p: BigUint, g: BigUint) -> impl Fn(Sender<Dh>, Receiver<Dh>) + Send + 'static {
+        move |tx, rx| {
+            let x = rand::thread_rng().gen_biguint_below(&p);
+            tx.send(Dh::Key(g.modpow(&x, &p))).unwrap();
+            if let Ok(Dh::Key(other)) = rx.recv() {
+                let secret = other.modpow(&x, &p);
+                tx.send(Dh::Data(secret.iter_u32_digits().next().unwrap_or(0)))
+                    .unwrap();
+            }
+            let _ = rx.recv();
+        }
+    }
+
+    #[test]
+    fn scenarios() {
+        let (p, g) = (BigUint::from(0xFFFFFFFBu32), BigUint::from(5u32));
+        let transcript = parameter_injection(p.clone()).run(
+            dh_party(p.clone(), g.clone()),
+            dh_party(p.clone(), g.clone()),
+        );
+        let data: Vec<Dh> = transcript
+            .iter()
+            .filter_map(|e| e.delivered.clone())
+            .collect();
+        assert!(data.contains(&Dh::Key(p.clone())));
+        assert_eq!(data.iter().filter(|&m| *m == Dh::Data(0)).count(), 2);
+
+        let (mitm, secrets) = key_substitution(p.clone(), g.clone());
+        let transcript = mitm.run(dh_party(p.clone(), g.clone()), dh_party(p, g));
+        let secrets = secrets.lock().unwrap().clone();
+        let low = |s: Option<BigUint>| Dh::Data(s.unwrap().iter_u32_digits().next().unwrap_or(0));
+        let sent = |direction| {
+            transcript
+                .iter()
+                .filter(|e| e.direction == direction)
+                .find_map(|e| e.intercepted.clone().filter(|m| matches!(m, Dh::Data(_))))
+        };
+        assert_eq!(sent(Direction::AToB), Some(low(secrets.with_a)));
+        assert_eq!(sent(Direction::BToA), Some(low(secrets.with_b)));
+    }
+}
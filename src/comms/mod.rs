@@ -4,6 +4,14 @@
 // Distributed under terms of the MIT license.
 //
 
+pub mod http;
+pub mod mitm;
+pub mod transport;
+pub mod wire;
+
+pub use transport::{Error, Transport};
+pub use wire::Wire;
+
 use std::sync::mpsc::*;
 
 pub fn comm_channel<T: Send + 'static>(a: impl Fn(Sender<T>, Receiver<T>) + Send + 'static, b: impl Fn(Sender<T>, Receiver<T>) + Send + 'static) {
@@ -0,0 +1,318 @@
+//
+// transport.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Sending typed messages between two parties
+//!
+//! `Channel` passes values between threads as is. `StreamTransport` frames each message as a `u32`
+//! length followed by its `Wire` encoding, so the two ends can be in separate processes, over TCP
+//! or a Unix socket.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use super::wire::Wire;
+
+/// Frames longer than this are rejected rather than allocated
+pub const MAX_FRAME: usize = 1 << 24;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The other end closed the connection
+    Disconnected,
+    /// Nothing arrived before the timeout
+    Timeout,
+    /// A frame couldn't be decoded
+    Malformed(String),
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Disconnected => write!(f, "disconnected"),
+            Error::Timeout => write!(f, "timed out"),
+            Error::Malformed(reason) => write!(f, "malformed message: {}", reason),
+            Error::Io(kind) => write!(f, "I/O error: {:?}", kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => Error::Disconnected,
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+            kind => Error::Io(kind),
+        }
+    }
+}
+
+/// One end of a two way connection carrying messages of type `T`
+pub trait Transport<T> {
+    fn send(&mut self, message: T) -> Result<(), Error>;
+
+    /// Waits for the next message, for at most the timeout if one is set
+    fn recv(&mut self) -> Result<T, Error>;
+
+    /// `None` waits forever, which is the default
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error>;
+}
+
+/// In memory transport between threads
+pub struct Channel<T> {
+    tx: Sender<T>,
+    rx: Receiver<T>,
+    timeout: Option<Duration>,
+}
+
+impl<T> Channel<T> {
+    /// Wraps the halves handed out by `comm_channel`
+    pub fn new(tx: Sender<T>, rx: Receiver<T>) -> Self {
+        Self {
+            tx,
+            rx,
+            timeout: None,
+        }
+    }
+
+    /// Two connected ends
+    pub fn pair() -> (Self, Self) {
+        let (atx, brx) = channel();
+        let (btx, arx) = channel();
+        (Self::new(atx, arx), Self::new(btx, brx))
+    }
+}
+
+impl<T> Transport<T> for Channel<T> {
+    fn send(&mut self, message: T) -> Result<(), Error> {
+        self.tx.send(message).map_err(|_| Error::Disconnected)
+    }
+
+    fn recv(&mut self) -> Result<T, Error> {
+        match self.timeout {
+            Some(timeout) => self.rx.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => Error::Timeout,
+                RecvTimeoutError::Disconnected => Error::Disconnected,
+            }),
+            None => self.rx.recv().map_err(|_| Error::Disconnected),
+        }
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+/// Writes `payload` with its length in front
+pub fn write_frame(stream: &mut impl Write, payload: &[u8]) -> Result<(), Error> {
+    if payload.len() > MAX_FRAME {
+        return Err(Error::Malformed(format!(
+            "frame of {} bytes is too long",
+            payload.len()
+        )));
+    }
+    stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+    stream.write_all(payload)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Reads one frame written by `write_frame`
+pub fn read_frame(stream: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(Error::Malformed(format!(
+            "frame of {} bytes is too long",
+            len
+        )));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// A byte stream that `StreamTransport` can run over
+pub trait Stream: Read + Write {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Length prefixed `Wire` messages over a byte stream
+///
+/// A timeout can go off partway through a frame, after which the stream is out of sync, so a
+/// transport that has timed out shouldn't be used again.
+pub struct StreamTransport<S: Stream, T: Wire> {
+    stream: S,
+    message: PhantomData<fn(T) -> T>,
+}
+
+impl<S: Stream, T: Wire> StreamTransport<S, T> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            message: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Stream, T: Wire> Transport<T> for StreamTransport<S, T> {
+    fn send(&mut self, message: T) -> Result<(), Error> {
+        write_frame(&mut self.stream, &message.to_wire())
+    }
+
+    fn recv(&mut self) -> Result<T, Error> {
+        T::from_wire(&read_frame(&mut self.stream)?)
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        // A zero duration is an error for sockets, rather than meaning no timeout
+        let timeout = timeout.map(|t| t.max(Duration::from_millis(1)));
+        Ok(self.stream.set_read_timeout(timeout)?)
+    }
+}
+
+impl<T: Wire> StreamTransport<TcpStream, T> {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr)?;
+        // Messages are small and sent one at a time, so don't wait to fill a packet
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+
+    /// Waits for the next connection on `listener`
+    pub fn accept(listener: &TcpListener) -> Result<Self, Error> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+
+    /// Two ends connected over a random loopback port
+    pub fn loopback() -> Result<(Self, Self), Error> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let client = Self::connect(listener.local_addr()?)?;
+        Ok((client, Self::accept(&listener)?))
+    }
+}
+
+#[cfg(unix)]
+impl<T: Wire> StreamTransport<UnixStream, T> {
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self::new(UnixStream::connect(path)?))
+    }
+
+    /// Waits for the next connection on `listener`
+    pub fn accept(listener: &UnixListener) -> Result<Self, Error> {
+        let (stream, _) = listener.accept()?;
+        Ok(Self::new(stream))
+    }
+
+    /// Two ends of an unnamed socket
+    pub fn pair() -> Result<(Self, Self), Error> {
+        let (a, b) = UnixStream::pair()?;
+        Ok((Self::new(a), Self::new(b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Bytes;
+    use num_bigint::BigUint;
+    use std::thread;
+
+    type Message = (String, (Bytes, BigUint));
+
+    fn echo(mut a: impl Transport<Message>, mut b: impl Transport<Message> + Send + 'static) {
+        let handle = thread::spawn(move || {
+            let (name, (bytes, n)) = b.recv().unwrap();
+            b.send((name.to_uppercase(), (bytes, n + 1u32))).unwrap();
+        });
+        let sent = Bytes::rand(100);
+        a.send(("alice".to_string(), (sent.clone(), BigUint::from(41u32))))
+            .unwrap();
+        assert_eq!(
+            a.recv().unwrap(),
+            ("ALICE".to_string(), (sent, BigUint::from(42u32)))
+        );
+        handle.join().unwrap();
+        // `b` is gone once the thread finishes
+        assert_eq!(a.recv(), Err(Error::Disconnected));
+    }
+
+    #[test]
+    fn channel() {
+        let (a, b) = Channel::pair();
+        echo(a, b);
+    }
+
+    #[test]
+    fn tcp() {
+        let (a, b) = StreamTransport::<TcpStream, _>::loopback().unwrap();
+        echo(a, b);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix() {
+        let (a, b) = StreamTransport::<UnixStream, _>::pair().unwrap();
+        echo(a, b);
+    }
+
+    #[test]
+    fn timeout() {
+        let (mut a, _b) = Channel::<u8>::pair();
+        a.set_timeout(Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(a.recv(), Err(Error::Timeout));
+
+        let (mut a, _b) = StreamTransport::<TcpStream, u8>::loopback().unwrap();
+        a.set_timeout(Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(a.recv(), Err(Error::Timeout));
+    }
+
+    #[test]
+    fn bad_frames() {
+        let (a, b) = StreamTransport::<TcpStream, u32>::loopback().unwrap();
+        let (mut raw, mut b) = (a.into_inner(), b);
+        raw.write_all(&[0, 0, 0, 2, 1, 2]).unwrap();
+        assert!(matches!(b.recv(), Err(Error::Malformed(_))));
+        raw.write_all(&[0xFF, 0xFF, 0xFF, 0xFF]).unwrap();
+        assert!(matches!(b.recv(), Err(Error::Malformed(_))));
+        drop(raw);
+        assert!(write_frame(&mut vec![], &vec![0; MAX_FRAME + 1]).is_err());
+    }
+}
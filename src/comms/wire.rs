@@ -0,0 +1,227 @@
+//
+// wire.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Binary encoding for protocol messages
+//!
+//! Integers are big endian and fixed width. Strings, `Bytes`, `BigUint`s and lists are prefixed
+//! with their length as a `u32`, so every message can be decoded without knowing its size up
+//! front. Enums are encoded as a tag byte followed by their fields.
+
+use num_bigint::BigUint;
+use std::convert::TryInto;
+
+use super::Error;
+use crate::data::Bytes;
+
+/// A value that can be sent over a `StreamTransport`
+pub trait Wire: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// Reads a value from the front of `input`, advancing it past what was read
+    fn decode(input: &mut &[u8]) -> Result<Self, Error>;
+
+    fn to_wire(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode(&mut out);
+        out
+    }
+
+    /// Decodes a complete frame, which must not have anything left over
+    fn from_wire(mut input: &[u8]) -> Result<Self, Error> {
+        let value = Self::decode(&mut input)?;
+        if !input.is_empty() {
+            return Err(Error::Malformed(format!("{} trailing bytes", input.len())));
+        }
+        Ok(value)
+    }
+}
+
+/// Splits `len` bytes off the front of `input`
+pub fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if input.len() < len {
+        return Err(Error::Malformed(format!(
+            "needed {} bytes, but only {} left",
+            len,
+            input.len()
+        )));
+    }
+    let (head, tail) = input.split_at(len);
+    *input = tail;
+    Ok(head)
+}
+
+/// The error for an enum tag that `decode` doesn't recognise
+pub fn unknown_tag<T>(tag: u8) -> Result<T, Error> {
+    Err(Error::Malformed(format!("unknown tag {}", tag)))
+}
+
+fn encode_len(len: usize, out: &mut Vec<u8>) {
+    (len as u32).encode(out)
+}
+
+fn decode_slice<'a>(input: &mut &'a [u8]) -> Result<&'a [u8], Error> {
+    let len = u32::decode(input)? as usize;
+    take(input, len)
+}
+
+macro_rules! wire_int {
+    ($($t:ty),*) => {
+        $(
+            impl Wire for $t {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+
+                fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+                    let bytes = take(input, std::mem::size_of::<$t>())?;
+                    Ok(<$t>::from_be_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+wire_int!(u8, u16, u32, u64);
+
+impl Wire for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u8).encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(Error::Malformed(format!("{} is not a bool", b))),
+        }
+    }
+}
+
+impl Wire for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        String::from_utf8(decode_slice(input)?.to_vec())
+            .map_err(|_| Error::Malformed("invalid UTF-8".to_string()))
+    }
+}
+
+impl Wire for Bytes {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        out.extend_from_slice(self);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        Ok(Bytes::from_vec(decode_slice(input)?.to_vec()))
+    }
+}
+
+impl Wire for BigUint {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let bytes = self.to_bytes_be();
+        encode_len(bytes.len(), out);
+        out.extend_from_slice(&bytes);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        Ok(BigUint::from_bytes_be(decode_slice(input)?))
+    }
+}
+
+impl<T: Wire> Wire for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        let len = u32::decode(input)? as usize;
+        // Not preallocated, since `len` hasn't been checked against the input yet
+        let mut items = vec![];
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Wire> Wire for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => 0u8.encode(out),
+            Some(value) => {
+                1u8.encode(out);
+                value.encode(out);
+            }
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => unknown_tag(tag),
+        }
+    }
+}
+
+impl<A: Wire, B: Wire> Wire for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Wire + PartialEq + std::fmt::Debug>(value: T) {
+        assert_eq!(T::from_wire(&value.to_wire()).unwrap(), value);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(0xABu8);
+        round_trip(0xDEADBEEFu32);
+        round_trip(u64::MAX);
+        round_trip(true);
+        round_trip("caf\u{e9}".to_string());
+        round_trip(Bytes::rand(40));
+        round_trip(Bytes::new());
+        round_trip(BigUint::from(0u32));
+        round_trip(BigUint::parse_bytes(b"123456789abcdef0123456789abcdef", 16).unwrap());
+        round_trip(vec![Some("a".to_string()), None]);
+        round_trip((Bytes::rand(3), 7u16));
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!("hi".to_string().to_wire(), [0, 0, 0, 2, b'h', b'i']);
+        assert_eq!(BigUint::from(0x0102u32).to_wire(), [0, 0, 0, 2, 1, 2]);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(u32::from_wire(&[0, 0, 1]).is_err());
+        assert!(u8::from_wire(&[1, 2]).is_err());
+        assert!(bool::from_wire(&[2]).is_err());
+        assert!(String::from_wire(&[0, 0, 0, 1, 0xFF]).is_err());
+        // A huge length shouldn't be trusted before the data arrives
+        assert!(Vec::<u8>::from_wire(&[0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+        assert!(Bytes::from_wire(&[0, 0, 0, 5, 1]).is_err());
+    }
+}
@@ -1,5 +1,25 @@
+use num_bigint::BigUint;
+
 use super::Bytes;
 
+impl Bytes {
+    /// I2OSP from PKCS #1: `n` as exactly `len` big endian bytes, or `None` if it doesn't fit
+    pub fn from_biguint(n: &BigUint, len: usize) -> Option<Bytes> {
+        let bytes = n.to_bytes_be();
+        // Zero is a single 0 byte rather than nothing
+        let bytes = if n.bits() == 0 { &[][..] } else { &bytes[..] };
+        if bytes.len() > len {
+            return None;
+        }
+        Some(Bytes::zero(len - bytes.len()) + bytes)
+    }
+
+    /// OS2IP from PKCS #1: the big endian integer, ignoring leading zeros
+    pub fn to_biguint(&self) -> BigUint {
+        BigUint::from_bytes_be(&self.bytes)
+    }
+}
+
 impl std::iter::FromIterator<u8> for Bytes {
     fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
         Self {
@@ -0,0 +1,130 @@
+//
+// ct.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Constant time comparison and selection
+//!
+//! None of these branch or index on secret data, so their running time only depends on the
+//! lengths of their inputs. Lengths are treated as public.
+
+use super::Bytes;
+use std::hint::black_box;
+
+/// Compares two slices without exiting on the first differing byte
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a
+        .iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    black_box(diff) == 0
+}
+
+/// All ones if `choice`, all zeros otherwise
+fn mask(choice: bool) -> u8 {
+    0u8.wrapping_sub(black_box(choice as u8))
+}
+
+/// Returns `a` if `choice` is set, otherwise `b`
+pub fn select(choice: bool, a: u8, b: u8) -> u8 {
+    b ^ (mask(choice) & (a ^ b))
+}
+
+/// Swaps the contents of `a` and `b` if `choice` is set
+///
+/// Panics if the slices have different lengths
+pub fn cswap(choice: bool, a: &mut [u8], b: &mut [u8]) {
+    assert_eq!(a.len(), b.len(), "Can only swap slices of the same length");
+    let mask = mask(choice);
+    for (a, b) in a.iter_mut().zip(b.iter_mut()) {
+        let t = mask & (*a ^ *b);
+        *a ^= t;
+        *b ^= t;
+    }
+}
+
+impl Bytes {
+    /// Constant time equality, unlike `==`
+    pub fn ct_eq(&self, other: &Bytes) -> bool {
+        ct_eq(&self.bytes, &other.bytes)
+    }
+
+    /// Returns a copy of `a` if `choice` is set, otherwise of `b`
+    ///
+    /// Panics if `a` and `b` have different lengths
+    pub fn ct_select(choice: bool, a: &Bytes, b: &Bytes) -> Bytes {
+        assert_eq!(a.len(), b.len(), "Can only select between equal lengths");
+        a.iter()
+            .zip(b.iter())
+            .map(|(&a, &b)| select(choice, a, b))
+            .collect()
+    }
+
+    /// Swaps `self` and `other` if `choice` is set
+    pub fn ct_swap(&mut self, other: &mut Bytes, choice: bool) {
+        cswap(choice, &mut self.bytes, &mut other.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::{leakage, LEAKAGE_THRESHOLD};
+
+    #[test]
+    fn eq() {
+        let a = Bytes::rand(32);
+        assert!(a.ct_eq(&a.clone()));
+        assert!(!a.ct_eq(&a.truncate(31)));
+        let mut b = a.clone();
+        b[31] ^= 0x80;
+        assert!(!a.ct_eq(&b));
+        assert!(Bytes::new().ct_eq(&Bytes::new()));
+    }
+
+    #[test]
+    fn select_swap() {
+        assert_eq!(select(true, 0xAB, 0x12), 0xAB);
+        assert_eq!(select(false, 0xAB, 0x12), 0x12);
+
+        let (a, b) = (Bytes::rand(16), Bytes::rand(16));
+        assert_eq!(Bytes::ct_select(true, &a, &b), a);
+        assert_eq!(Bytes::ct_select(false, &a, &b), b);
+
+        let (mut x, mut y) = (a.clone(), b.clone());
+        x.ct_swap(&mut y, false);
+        assert_eq!((&x, &y), (&a, &b));
+        x.ct_swap(&mut y, true);
+        assert_eq!((&x, &y), (&b, &a));
+    }
+
+    /// Times comparing against an equal value versus one that differs in the first byte
+    fn compare_leakage(eq: impl Fn(&Bytes, &Bytes) -> bool) -> f64 {
+        let secret = Bytes::rand(1 << 14);
+        let same = secret.clone();
+        let mut differs = secret.clone();
+        differs[0] ^= 1;
+        leakage(
+            || {
+                black_box(eq(black_box(&secret), black_box(&same)));
+            },
+            || {
+                black_box(eq(black_box(&secret), black_box(&differs)));
+            },
+            2000,
+        )
+    }
+
+    // Wall clock timing is too noisy to assert on in an ordinary test run, so this is run by hand
+    // with `cargo test --release -- --ignored constant_time`
+    #[test]
+    #[ignore = "timing measurement, run by hand in release mode"]
+    fn constant_time() {
+        assert!(compare_leakage(|a, b| a == b).abs() > LEAKAGE_THRESHOLD);
+        assert!(compare_leakage(|a, b| a.ct_eq(b)).abs() < LEAKAGE_THRESHOLD);
+    }
+}
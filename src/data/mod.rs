@@ -1,4 +1,5 @@
 mod conversion;
+pub mod ct;
 mod display;
 mod ops;
 
@@ -687,4 +688,45 @@ impl Bytes {
         }
         ret
     }
+    /**
+     * Base 64 encodes without padding, using the given alphabet
+     * (like the variants used by PHC strings and bcrypt)
+     */
+    pub fn to_64_with(&self, alphabet: &[u8; 64]) -> String {
+        let mut ret = String::with_capacity((self.len() * 4).div_ceil(3));
+        for chunk in self.bytes.chunks(3) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, &b)| n | ((b as u32) << (16 - 8 * i)));
+            for i in 0..=chunk.len() {
+                ret.push(alphabet[((n >> (18 - 6 * i)) & 0x3F) as usize] as char);
+            }
+        }
+        ret
+    }
+    /**
+     * Reverses `to_64_with`, returning None on characters outside
+     * the alphabet or an impossible length
+     */
+    pub fn read_64_with(s: &str, alphabet: &[u8; 64]) -> Option<Bytes> {
+        let sextets = s
+            .bytes()
+            .map(|c| alphabet.iter().position(|&a| a == c).map(|i| i as u32))
+            .collect::<Option<Vec<_>>>()?;
+        if sextets.len() % 4 == 1 {
+            return None;
+        }
+        let mut ret = Vec::with_capacity(sextets.len() * 3 / 4);
+        for chunk in sextets.chunks(4) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, &s)| n | (s << (18 - 6 * i)));
+            for i in 0..chunk.len() - 1 {
+                ret.push((n >> (16 - 8 * i)) as u8);
+            }
+        }
+        Some(Bytes { bytes: ret })
+    }
 }
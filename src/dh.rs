@@ -0,0 +1,614 @@
+//
+// dh.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Diffie-Hellman with a negotiated group, followed by an AES-CBC echo, as in challenges 34 and 35
+//!
+//! ```text
+//! Client                                  Server
+//! Negotiate { p, g, q }               ->
+//!                                     <-  Accept
+//! PublicKey(A)                        ->
+//!                                     <-  PublicKey(B)
+//! Data { iv, AES-CBC(K, message) }    ->
+//!                                     <-  Data { iv', AES-CBC(K, message) }
+//! ```
+//!
+//! K is `DhGroup::session_key` of the shared secret. The echo doubles as key confirmation, since
+//! the client only accepts the session if its message comes back intact.
+
+use num_bigint::BigUint;
+
+use crate::cipher::diffie::{
+    DhError, DhGroup, FFDHE_2048, FFDHE_3072, FFDHE_4096, MODP_1536, MODP_2048, MODP_3072,
+    MODP_4096,
+};
+use crate::cipher::{aes_cbc_de, aes_cbc_en, BLOCK_SIZE};
+use crate::comms::{self, Transport, Wire};
+use crate::data::Bytes;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Negotiate(DhGroup),
+    Accept,
+    PublicKey(BigUint),
+    Data {
+        iv: Bytes,
+        ciphertext: Bytes,
+    },
+    /// Sent by the server in place of a reply when it gives up
+    Failed,
+}
+
+impl Wire for DhGroup {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.p.encode(out);
+        self.g.encode(out);
+        self.q.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, comms::Error> {
+        Ok(DhGroup {
+            p: BigUint::decode(input)?,
+            g: BigUint::decode(input)?,
+            q: Option::decode(input)?,
+        })
+    }
+}
+
+impl Wire for Message {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::Negotiate(group) => {
+                0u8.encode(out);
+                group.encode(out);
+            }
+            Message::Accept => 1u8.encode(out),
+            Message::PublicKey(key) => {
+                2u8.encode(out);
+                key.encode(out);
+            }
+            Message::Data { iv, ciphertext } => {
+                3u8.encode(out);
+                iv.encode(out);
+                ciphertext.encode(out);
+            }
+            Message::Failed => 4u8.encode(out),
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, comms::Error> {
+        Ok(match u8::decode(input)? {
+            0 => Message::Negotiate(DhGroup::decode(input)?),
+            1 => Message::Accept,
+            2 => Message::PublicKey(BigUint::decode(input)?),
+            3 => Message::Data {
+                iv: Bytes::decode(input)?,
+                ciphertext: Bytes::decode(input)?,
+            },
+            4 => Message::Failed,
+            tag => return comms::wire::unknown_tag(tag),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A message arrived out of order
+    UnexpectedMessage,
+    /// The server doesn't allow the group the client asked for
+    UnsupportedGroup,
+    InvalidPublicKey(DhError),
+    /// The ciphertext didn't decrypt to something with valid padding
+    BadCiphertext,
+    /// The echo didn't match what was sent, so the two sides have different keys
+    BadEcho,
+    /// The server sent `Failed`
+    Rejected,
+    /// The connection failed, or the other side sent something undecodable
+    Transport(comms::Error),
+}
+
+impl From<comms::Error> for Error {
+    fn from(e: comms::Error) -> Self {
+        Error::Transport(e)
+    }
+}
+
+impl From<DhError> for Error {
+    fn from(e: DhError) -> Self {
+        Error::InvalidPublicKey(e)
+    }
+}
+
+/// Encrypts `message` under `key` with a fresh IV
+pub fn seal(key: &Bytes, message: &Bytes) -> Message {
+    let iv = Bytes::rand(BLOCK_SIZE);
+    Message::Data {
+        ciphertext: aes_cbc_en(message.pad_pkcs7(BLOCK_SIZE), key.clone(), iv.clone()),
+        iv,
+    }
+}
+
+/// Decrypts the contents of a `Data` message, checking the padding
+pub fn open(key: &Bytes, iv: Bytes, ciphertext: Bytes) -> Result<Bytes, Error> {
+    if ciphertext.is_empty() || ciphertext.len() % BLOCK_SIZE != 0 || iv.len() != BLOCK_SIZE {
+        return Err(Error::BadCiphertext);
+    }
+    let padded = aes_cbc_de(ciphertext, key.clone(), iv);
+    let pad = padded[padded.len() - 1] as usize;
+    if pad == 0
+        || pad > BLOCK_SIZE
+        || padded[padded.len() - pad..]
+            .iter()
+            .any(|&b| b as usize != pad)
+    {
+        return Err(Error::BadCiphertext);
+    }
+    Ok(padded.truncate(padded.len() - pad))
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    group: DhGroup,
+    check_public_key: bool,
+}
+
+impl Client {
+    pub fn new(group: DhGroup) -> Self {
+        Self {
+            group,
+            check_public_key: true,
+        }
+    }
+
+    /// Skips validating B, so a server (or anyone in between) can force the shared secret
+    pub fn insecure(mut self) -> Self {
+        self.check_public_key = false;
+        self
+    }
+
+    /// Runs the exchange and sends `message`, returning the session key once it's echoed back
+    pub fn run(
+        &self,
+        transport: &mut impl Transport<Message>,
+        message: &Bytes,
+    ) -> Result<Bytes, Error> {
+        transport.send(Message::Negotiate(self.group.clone()))?;
+        expect(transport.recv()?, |m| match m {
+            Message::Accept => Some(()),
+            _ => None,
+        })?;
+        let (a, a_pub) = self.group.keypair();
+        transport.send(Message::PublicKey(a_pub))?;
+        let b_pub = expect(transport.recv()?, |m| match m {
+            Message::PublicKey(key) => Some(key),
+            _ => None,
+        })?;
+        let s = shared_secret(&self.group, &a, &b_pub, self.check_public_key)?;
+        let key = self.group.session_key(&s);
+        transport.send(seal(&key, message))?;
+        let (iv, ciphertext) = expect(transport.recv()?, |m| match m {
+            Message::Data { iv, ciphertext } => Some((iv, ciphertext)),
+            _ => None,
+        })?;
+        if !open(&key, iv, ciphertext)?.ct_eq(message) {
+            return Err(Error::BadEcho);
+        }
+        Ok(key)
+    }
+}
+
+fn shared_secret(
+    group: &DhGroup,
+    private: &BigUint,
+    their_public: &BigUint,
+    check_public_key: bool,
+) -> Result<BigUint, Error> {
+    if check_public_key {
+        Ok(group.shared_secret(private, their_public)?)
+    } else {
+        Ok(their_public.modpow(private, &group.p))
+    }
+}
+
+/// Unwraps the message `f` picks out, or turns `Failed` and anything else into errors
+fn expect<T>(message: Message, f: impl FnOnce(Message) -> Option<T>) -> Result<T, Error> {
+    if message == Message::Failed {
+        return Err(Error::Rejected);
+    }
+    f(message).ok_or(Error::UnexpectedMessage)
+}
+
+/// Whether a group the client picked is safe to compute in: p is odd and greater than 3, and q,
+/// if given, is greater than 2 and divides p - 1. With `check_g`, g must also be in (1, p - 1),
+/// since 1 and p - 1 generate trivial subgroups and anything else is out of range
+fn well_formed(group: &DhGroup, check_g: bool) -> bool {
+    let DhGroup { p, g, q } = group;
+    let one = BigUint::from(1u32);
+    if !p.bit(0) || *p <= BigUint::from(3u32) {
+        return false;
+    }
+    if check_g && !(*g > one && *g < p - 1u32) {
+        return false;
+    }
+    match q {
+        Some(q) => *q > BigUint::from(2u32) && ((p - 1u32) % q).bits() == 0,
+        None => true,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Server {
+    /// `None` accepts any group
+    groups: Option<Vec<DhGroup>>,
+    check_public_key: bool,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    /// A server that allows the RFC 3526 and RFC 7919 groups
+    pub fn new() -> Self {
+        let groups = [
+            &*MODP_1536,
+            &*MODP_2048,
+            &*MODP_3072,
+            &*MODP_4096,
+            &*FFDHE_2048,
+            &*FFDHE_3072,
+            &*FFDHE_4096,
+        ];
+        Self {
+            groups: Some(groups.iter().map(|&g| g.clone()).collect()),
+            check_public_key: true,
+        }
+    }
+
+    /// Also allows `group`
+    pub fn allow(mut self, group: DhGroup) -> Self {
+        if let Some(groups) = &mut self.groups {
+            groups.push(group);
+        }
+        self
+    }
+
+    /// Uses whatever group the client asks for, which lets a man in the middle pick it instead
+    pub fn any_group(mut self) -> Self {
+        self.groups = None;
+        self
+    }
+
+    /// Skips validating A, and g in a group the client picked
+    pub fn insecure(mut self) -> Self {
+        self.check_public_key = false;
+        self
+    }
+
+    fn allows(&self, group: &DhGroup) -> bool {
+        match &self.groups {
+            Some(groups) => groups.contains(group),
+            None => well_formed(group, self.check_public_key),
+        }
+    }
+
+    /// Runs one exchange, echoing the client's message and returning it with the session key
+    ///
+    /// Failures are reported to the client with `Message::Failed`, as well as returned
+    pub fn run(&self, transport: &mut impl Transport<Message>) -> Result<(Bytes, Bytes), Error> {
+        let result = self.exchange(transport);
+        match &result {
+            // There's no one left to tell
+            Ok(_) | Err(Error::Transport(_)) => (),
+            Err(_) => {
+                // The client may already be gone, which doesn't change the outcome
+                let _ = transport.send(Message::Failed);
+            }
+        }
+        result
+    }
+
+    fn exchange(&self, transport: &mut impl Transport<Message>) -> Result<(Bytes, Bytes), Error> {
+        let group = expect(transport.recv()?, |m| match m {
+            Message::Negotiate(group) => Some(group),
+            _ => None,
+        })?;
+        if !self.allows(&group) {
+            return Err(Error::UnsupportedGroup);
+        }
+        transport.send(Message::Accept)?;
+        let a_pub = expect(transport.recv()?, |m| match m {
+            Message::PublicKey(key) => Some(key),
+            _ => None,
+        })?;
+        let (b, b_pub) = group.keypair();
+        let s = shared_secret(&group, &b, &a_pub, self.check_public_key)?;
+        let key = group.session_key(&s);
+        transport.send(Message::PublicKey(b_pub))?;
+        let (iv, ciphertext) = expect(transport.recv()?, |m| match m {
+            Message::Data { iv, ciphertext } => Some((iv, ciphertext)),
+            _ => None,
+        })?;
+        let message = open(&key, iv, ciphertext)?;
+        transport.send(seal(&key, &message))?;
+        Ok((key, message))
+    }
+}
+
+/// Negotiating a malicious g, from challenge 35
+///
+/// A man in the middle replaces g in the client's `Negotiate` with 1, p or p - 1. The server's
+/// B = g^b is then 1, 0 or +-1, so the client's secret B^a is predictable. The client's A is
+/// replaced with the same g, which makes the server's secret g^b = B, known as well. With both
+/// keys the proxy can read and re-encrypt everything, and the echo still checks out.
+///
+/// This only works against a server that accepts any group, and a client that doesn't check B.
+pub mod malicious_g {
+    use super::*;
+    use crate::comms::mitm::{Direction, Mitm};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Substitution {
+        One,
+        P,
+        PMinusOne,
+    }
+
+    impl Substitution {
+        pub fn g(&self, p: &BigUint) -> BigUint {
+            match self {
+                Substitution::One => BigUint::from(1u32),
+                Substitution::P => p.clone(),
+                Substitution::PMinusOne => p - 1u32,
+            }
+        }
+    }
+
+    /// The possible values of the client's secret B^a, given the B it received
+    ///
+    /// For g = p - 1, B = 1 when b is even, or p - 1 when it's odd, in which case B^a depends on
+    /// whether a is even, and there are two candidates
+    pub fn client_secrets(p: &BigUint, b_pub: &BigUint) -> Vec<BigUint> {
+        let one = BigUint::from(1u32);
+        if *b_pub == p - 1u32 {
+            vec![one, p - 1u32]
+        } else if (b_pub % p).bits() == 0 {
+            vec![BigUint::from(0u32)]
+        } else {
+            vec![one]
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct State {
+        group: Option<DhGroup>,
+        client_keys: Vec<Bytes>,
+        server_key: Option<Bytes>,
+        plaintexts: Vec<Bytes>,
+    }
+
+    /// Decrypts with whichever of `keys` gives valid padding
+    fn open_any(keys: &[Bytes], iv: &Bytes, ciphertext: &Bytes) -> Option<(Bytes, Bytes)> {
+        keys.iter().find_map(|key| {
+            let message = open(key, iv.clone(), ciphertext.clone()).ok()?;
+            Some((key.clone(), message))
+        })
+    }
+
+    /// A proxy carrying out the attack, and the plaintexts it has read so far
+    pub fn attack(substitution: Substitution) -> (Mitm<Message>, Arc<Mutex<Vec<Bytes>>>) {
+        let state = Arc::new(Mutex::new(State::default()));
+        let plaintexts = Arc::new(Mutex::new(vec![]));
+        let (to_server, to_client) = (state.clone(), state);
+        let (server_read, client_read) = (plaintexts.clone(), plaintexts.clone());
+        let mitm = Mitm::new()
+            .rewrite(Direction::AToB, move |message| {
+                let mut state = to_server.lock().unwrap();
+                match message {
+                    Message::Negotiate(mut group) => {
+                        group.g = substitution.g(&group.p);
+                        state.group = Some(group.clone());
+                        Message::Negotiate(group)
+                    }
+                    Message::PublicKey(_) => match &state.group {
+                        Some(group) => Message::PublicKey(group.g.clone()),
+                        None => Message::Failed,
+                    },
+                    Message::Data { iv, ciphertext } => {
+                        let server_key = state.server_key.clone();
+                        match (open_any(&state.client_keys, &iv, &ciphertext), server_key) {
+                            (Some((key, message)), Some(server_key)) => {
+                                // Only the key that worked is used for the echo
+                                state.client_keys = vec![key];
+                                server_read.lock().unwrap().push(message.clone());
+                                seal(&server_key, &message)
+                            }
+                            _ => Message::Data { iv, ciphertext },
+                        }
+                    }
+                    message => message,
+                }
+            })
+            .rewrite(Direction::BToA, move |message| {
+                let mut state = to_client.lock().unwrap();
+                let state = &mut *state;
+                match (message, &state.group) {
+                    (Message::PublicKey(b_pub), Some(group)) => {
+                        state.client_keys = client_secrets(&group.p, &b_pub)
+                            .iter()
+                            .map(|s| group.session_key(s))
+                            .collect();
+                        state.server_key = Some(group.session_key(&b_pub));
+                        Message::PublicKey(b_pub)
+                    }
+                    (Message::Data { iv, ciphertext }, _) => {
+                        let key = state.server_key.as_ref();
+                        match (
+                            key.and_then(|k| open(k, iv.clone(), ciphertext.clone()).ok()),
+                            state.client_keys.first(),
+                        ) {
+                            (Some(message), Some(client_key)) => {
+                                client_read.lock().unwrap().push(message.clone());
+                                seal(client_key, &message)
+                            }
+                            _ => Message::Data { iv, ciphertext },
+                        }
+                    }
+                    (message, _) => message,
+                }
+            });
+        (mitm, plaintexts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comms::transport::{Channel, StreamTransport};
+    use std::net::TcpStream;
+    use std::thread;
+
+    fn exchange(
+        client: Client,
+        server: Server,
+        mut a: impl Transport<Message>,
+        mut b: impl Transport<Message> + Send + 'static,
+    ) -> (Result<Bytes, Error>, Result<(Bytes, Bytes), Error>) {
+        let handle = thread::spawn(move || server.run(&mut b));
+        let result = client.run(&mut a, &Bytes::read_utf8("Simple Msg"));
+        (result, handle.join().unwrap())
+    }
+
+    #[test]
+    fn echo() {
+        let (a, b) = Channel::pair();
+        let (client, server) = exchange(Client::new(MODP_1536.clone()), Server::new(), a, b);
+        let (key, message) = server.unwrap();
+        assert_eq!(client, Ok(key));
+        assert_eq!(message, Bytes::read_utf8("Simple Msg"));
+
+        let (a, b) = StreamTransport::<TcpStream, _>::loopback().unwrap();
+        let (client, server) = exchange(Client::new(FFDHE_2048.clone()), Server::new(), a, b);
+        assert_eq!(client, Ok(server.unwrap().0));
+    }
+
+    #[test]
+    fn negotiation() {
+        let custom = DhGroup::new(BigUint::from(0xFFFFFFFBu32), BigUint::from(5u32));
+        let (a, b) = Channel::pair();
+        let (client, server) = exchange(Client::new(custom.clone()), Server::new(), a, b);
+        assert_eq!(client, Err(Error::Rejected));
+        assert_eq!(server, Err(Error::UnsupportedGroup));
+
+        for server in [
+            Server::new().allow(custom.clone()),
+            Server::new().any_group(),
+        ] {
+            let (a, b) = Channel::pair();
+            let (client, server) = exchange(Client::new(custom.clone()), server, a, b);
+            assert_eq!(client, Ok(server.unwrap().0));
+        }
+    }
+
+    #[test]
+    fn malformed_group() {
+        let (p, g) = (&MODP_1536.p, &MODP_1536.g);
+        let q = MODP_1536.q.clone().unwrap();
+        for group in [
+            DhGroup::new(BigUint::from(0u32), g.clone()),
+            DhGroup::new(BigUint::from(3u32), BigUint::from(2u32)),
+            DhGroup::new(p + 1u32, g.clone()),
+            DhGroup::new(p.clone(), BigUint::from(1u32)),
+            DhGroup::new(p.clone(), p - 1u32),
+            DhGroup::new(p.clone(), p.clone()),
+            DhGroup::new(p.clone(), g.clone()).order(BigUint::from(2u32)),
+            DhGroup::new(p.clone(), g.clone()).order(&q + 1u32),
+        ] {
+            let (a, b) = Channel::pair();
+            let server = Server::new().any_group();
+            let (client, server) = exchange(Client::new(group), server, a, b);
+            assert_eq!(client, Err(Error::Rejected));
+            assert_eq!(server, Err(Error::UnsupportedGroup));
+        }
+        // An insecure server still needs a group it can compute in, but takes any g
+        assert!(well_formed(&DhGroup::new(p.clone(), p - 1u32), false));
+        assert!(!well_formed(
+            &DhGroup::new(BigUint::from(0u32), g.clone()),
+            false
+        ));
+        assert!(!well_formed(
+            &MODP_1536.clone().order(BigUint::from(0u32)),
+            false
+        ));
+    }
+
+    #[test]
+    fn bad_public_key() {
+        let (mut a, mut b) = Channel::pair();
+        let handle = thread::spawn(move || Server::new().run(&mut b));
+        a.send(Message::Negotiate(MODP_1536.clone())).unwrap();
+        assert_eq!(a.recv(), Ok(Message::Accept));
+        a.send(Message::PublicKey(MODP_1536.p.clone())).unwrap();
+        assert_eq!(a.recv(), Ok(Message::Failed));
+        assert_eq!(
+            handle.join().unwrap(),
+            Err(Error::InvalidPublicKey(DhError::OutOfRange))
+        );
+    }
+
+    #[test]
+    fn padding() {
+        let key = Bytes::rand(16);
+        let message = Bytes::rand(32);
+        match seal(&key, &message) {
+            Message::Data { iv, ciphertext } => {
+                assert_eq!(ciphertext.len(), 48);
+                assert_eq!(open(&key, iv.clone(), ciphertext.clone()), Ok(message));
+                assert_eq!(
+                    open(&key, iv, ciphertext.truncate(20)),
+                    Err(Error::BadCiphertext)
+                );
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn malicious_secrets() {
+        use malicious_g::{client_secrets, Substitution};
+        let p = &MODP_1536.p;
+        let (a, _) = MODP_1536.keypair();
+        for substitution in [Substitution::One, Substitution::P, Substitution::PMinusOne] {
+            let g = substitution.g(p);
+            let (b, _) = MODP_1536.keypair();
+            let b_pub = g.modpow(&b, p);
+            let secret = b_pub.modpow(&a, p);
+            assert!(client_secrets(p, &b_pub).contains(&secret));
+        }
+        assert_eq!(
+            client_secrets(p, &(p - 1u32)),
+            vec![BigUint::from(1u32), p - 1u32]
+        );
+    }
+
+    #[test]
+    fn message_wire() {
+        for message in [
+            Message::Negotiate(FFDHE_2048.clone()),
+            Message::Negotiate(DhGroup::new(BigUint::from(23u32), BigUint::from(5u32))),
+            Message::Accept,
+            Message::PublicKey(BigUint::from(12345u32)),
+            seal(&Bytes::rand(16), &Bytes::rand(5)),
+            Message::Failed,
+        ] {
+            assert_eq!(Message::from_wire(&message.to_wire()), Ok(message));
+        }
+    }
+}
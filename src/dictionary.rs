@@ -0,0 +1,229 @@
+//
+// dictionary.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Offline dictionary attacks
+//!
+//! Candidates are generated from a wordlist, with mangling rules applied to each word, and checked
+//! in parallel against a closure. The closure is what knows about the hash being attacked, so the
+//! same cracker works for salted hashes, MACs, or anything else that can be recomputed offline.
+
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// A mangling rule. Each rule adds variants of every candidate produced by the rules before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    /// `password` -> `Password`
+    Capitalize,
+    /// `password` -> `PASSWORD`
+    Upper,
+    /// `password` -> `p455w0rd`
+    Leet,
+    /// Appends every number with up to this many digits, e.g. `password7` and `password07`
+    Digits(u32),
+}
+
+impl Rule {
+    fn apply(&self, word: &str) -> Vec<String> {
+        match self {
+            Rule::Capitalize => {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => vec![first.to_uppercase().chain(chars).collect()],
+                    None => vec![],
+                }
+            }
+            Rule::Upper => vec![word.to_uppercase()],
+            Rule::Leet => vec![word
+                .chars()
+                .map(|c| match c.to_ascii_lowercase() {
+                    'a' => '4',
+                    'e' => '3',
+                    'i' => '1',
+                    'o' => '0',
+                    's' => '5',
+                    't' => '7',
+                    _ => c,
+                })
+                .collect()],
+            Rule::Digits(max) => (1..=*max)
+                .flat_map(|digits| {
+                    (0..10u64.pow(digits))
+                        .map(move |n| format!("{}{:0width$}", word, n, width = digits as usize))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Every candidate for `word` under `rules`, without duplicates
+pub fn mangle(word: &str, rules: &[Rule]) -> Vec<String> {
+    let mut candidates = vec![word.to_string()];
+    for rule in rules {
+        let extra: Vec<String> = candidates.iter().flat_map(|c| rule.apply(c)).collect();
+        candidates.extend(extra);
+    }
+    let mut seen = BTreeSet::new();
+    candidates.retain(|c| seen.insert(c.clone()));
+    candidates
+}
+
+/// Result of a dictionary attack
+#[derive(Debug, Clone)]
+pub struct Cracked {
+    /// The candidate that was accepted, if any
+    pub password: Option<String>,
+    /// Candidates checked, including ones that were in flight when the password was found
+    pub candidates: usize,
+    pub elapsed: Duration,
+}
+
+impl Cracked {
+    /// Candidates checked per second
+    pub fn throughput(&self) -> f64 {
+        self.candidates as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+enum Source {
+    File(PathBuf),
+    Words(Vec<String>),
+}
+
+pub struct Dictionary {
+    source: Source,
+    rules: Vec<Rule>,
+}
+
+impl Dictionary {
+    /// Words are read from the file one per line as the attack runs, rather than up front
+    pub fn from_file(path: impl AsRef<Path>) -> Self {
+        Self {
+            source: Source::File(path.as_ref().to_path_buf()),
+            rules: vec![],
+        }
+    }
+
+    pub fn from_words(words: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            source: Source::Words(words.into_iter().map(|w| w.into()).collect()),
+            rules: vec![],
+        }
+    }
+
+    /// Adds a mangling rule, applied after the ones already added
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Checks candidates with `verify` in parallel, stopping at the first one accepted
+    pub fn crack(&self, verify: impl Fn(&str) -> bool + Sync) -> io::Result<Cracked> {
+        let start = Instant::now();
+        let count = AtomicUsize::new(0);
+        let check = |word: String| {
+            mangle(word.trim(), &self.rules)
+                .into_iter()
+                .find(|candidate| {
+                    count.fetch_add(1, Ordering::Relaxed);
+                    verify(candidate)
+                })
+        };
+        let password = match &self.source {
+            Source::File(path) => {
+                let lines = BufReader::new(File::open(path)?).lines();
+                // Read errors end the attack early, rather than being silently skipped
+                let error = std::sync::Mutex::new(None);
+                let found = lines
+                    .map_while(|line| match line {
+                        Ok(line) => Some(line),
+                        Err(e) => {
+                            *error.lock().unwrap() = Some(e);
+                            None
+                        }
+                    })
+                    .par_bridge()
+                    .find_map_any(check);
+                if let Some(e) = error.into_inner().unwrap() {
+                    return Err(e);
+                }
+                found
+            }
+            Source::Words(words) => words.iter().cloned().par_bridge().find_map_any(check),
+        };
+        Ok(Cracked {
+            password,
+            candidates: count.into_inner(),
+            elapsed: start.elapsed(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Bytes;
+    use crate::hash::{hmac::Hmac, sha2::Sha256, Digest};
+
+    #[test]
+    fn rules() {
+        assert_eq!(mangle("test", &[Rule::Capitalize]), vec!["test", "Test"]);
+        assert_eq!(
+            mangle("toast", &[Rule::Upper, Rule::Leet]),
+            vec!["toast", "TOAST", "70457"]
+        );
+        let digits = mangle("a", &[Rule::Digits(2)]);
+        assert_eq!(digits.len(), 1 + 10 + 100);
+        assert!(digits.contains(&"a7".to_string()));
+        assert!(digits.contains(&"a07".to_string()));
+        assert!(mangle("", &[Rule::Capitalize]) == vec![""]);
+    }
+
+    #[test]
+    fn salted_sha() {
+        let salt = Bytes::rand(16);
+        let hash = |passwd: &str| {
+            let mut hasher = Sha256::default();
+            hasher.update(&salt);
+            hasher.update(passwd.as_bytes());
+            hasher.finalize()
+        };
+        let target = hash("W4lru542");
+        let cracked = Dictionary::from_words(vec!["seal", "walrus", "otter"])
+            .rule(Rule::Capitalize)
+            .rule(Rule::Leet)
+            .rule(Rule::Digits(2))
+            .crack(|candidate| hash(candidate) == target)
+            .unwrap();
+        assert_eq!(cracked.password.as_deref(), Some("W4lru542"));
+        assert!(cracked.candidates > 0);
+        assert!(cracked.throughput() > 0.0);
+    }
+
+    #[test]
+    fn wordlist_hmac() {
+        let key = Bytes::rand(16);
+        let tag = Hmac::<Sha256>::mac(b"pyramid", &key);
+        let cracked = Dictionary::from_file("wordlist")
+            .crack(|candidate| Hmac::<Sha256>::mac(candidate.as_bytes(), &key) == tag)
+            .unwrap();
+        assert_eq!(cracked.password.as_deref(), Some("pyramid"));
+
+        let missing = Dictionary::from_words(vec!["aardvark"])
+            .crack(|_| false)
+            .unwrap();
+        assert_eq!(missing.password, None);
+        assert_eq!(missing.candidates, 1);
+        assert!(Dictionary::from_file("no such wordlist")
+            .crack(|_| true)
+            .is_err());
+    }
+}
@@ -28,7 +28,36 @@
 // # Miller test (deterministic version), depends on the reinman hypothesis
 // run a from 2 <= a <= ln(p)^2
 
-use num_bigint::BigUint;
+use lazy_static::lazy_static;
+use num_bigint::{BigInt, BigUint, RandBigInt};
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+/// Random bases used by `miller_rabin_prime_test`, for a 4^-40 chance of passing a composite
+pub const MILLER_RABIN_ROUNDS: usize = 40;
+
+/// Bases that make Miller-Rabin exact for n < 3.3 * 10^24, which covers every u64
+const DETERMINISTIC_BASES: &[u32] = &[2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41];
+
+lazy_static! {
+    /// Primes below 2000, for trial division before the expensive tests
+    pub static ref SMALL_PRIMES: Vec<u32> = sieve(2000);
+}
+
+/// The primes below `bound`, by the sieve of Eratosthenes
+pub fn sieve(bound: u32) -> Vec<u32> {
+    let bound = bound as usize;
+    let mut composite = vec![false; bound];
+    let mut primes = vec![];
+    for i in 2..bound {
+        if !composite[i] {
+            primes.push(i as u32);
+            for j in (i * i..bound).step_by(i) {
+                composite[j] = true;
+            }
+        }
+    }
+    primes
+}
 
 pub fn is_even(p: BigUint) -> bool {
     if let Some(least_sig_digit) = p.iter_u32_digits().next() {
@@ -42,21 +71,248 @@ pub fn is_even(p: BigUint) -> bool {
     }
 }
 
+/// Settles n by trial division by `SMALL_PRIMES`, or returns `None` if it's too big to tell
+fn trial_division(n: &BigUint) -> Option<bool> {
+    for &p in SMALL_PRIMES.iter() {
+        if *n == BigUint::from(p) {
+            return Some(true);
+        }
+        if (n % p).is_zero() {
+            return Some(false);
+        }
+    }
+    let largest = *SMALL_PRIMES.last().unwrap();
+    if *n < BigUint::from(largest) * largest {
+        // Not 0 or 1, since those are smaller than any prime and aren't divisible by it
+        Some(*n > BigUint::one())
+    } else {
+        None
+    }
+}
+
+/// Whether odd n > 2 is a strong probable prime to base a
+fn strong_probable_prime(n: &BigUint, a: &BigUint) -> bool {
+    let n_1 = n - 1u32;
+    let s = n_1.trailing_zeros().unwrap();
+    let d = &n_1 >> s;
+    let mut x = a.modpow(&d, n);
+    if x.is_one() || x == n_1 {
+        return true;
+    }
+    for _ in 1..s {
+        x = &x * &x % n;
+        if x == n_1 {
+            return true;
+        }
+        if x.is_one() {
+            return false;
+        }
+    }
+    false
+}
+
+/// Miller-Rabin, with `rounds` random bases
+///
+/// Below 2^64 the fixed `DETERMINISTIC_BASES` are used instead, so the answer is exact.
+pub fn miller_rabin(n: &BigUint, rounds: usize) -> bool {
+    if let Some(prime) = trial_division(n) {
+        return prime;
+    }
+    if n.bits() <= 64 {
+        return DETERMINISTIC_BASES
+            .iter()
+            .all(|&a| strong_probable_prime(n, &BigUint::from(a)));
+    }
+    let mut rng = rand::thread_rng();
+    let top = n - 1u32;
+    (0..rounds)
+        .all(|_| strong_probable_prime(n, &rng.gen_biguint_range(&BigUint::from(2u32), &top)))
+}
+
 pub fn miller_rabin_prime_test(p: BigUint) -> bool {
-    // Find d
-    let mut s = BigUint::from(1usize);
-    let mut tmp = &p / 2usize;
-    let d = loop {
+    miller_rabin(&p, MILLER_RABIN_ROUNDS)
+}
 
+/// The Jacobi symbol (a/n), for odd n > 0
+pub fn jacobi(a: &BigInt, n: &BigUint) -> i8 {
+    let mut n = BigInt::from(n.clone());
+    let mut a = ((a % &n) + &n) % &n;
+    let mut result = 1;
+    while !a.is_zero() {
+        let twos = a.trailing_zeros().unwrap();
+        a >>= twos;
+        // (2/n) is -1 when n = 3 or 5 mod 8
+        let n_mod_8 = (&n % 8u32).to_u8().unwrap();
+        if twos % 2 == 1 && (n_mod_8 == 3 || n_mod_8 == 5) {
+            result = -result;
+        }
+        // Quadratic reciprocity flips the sign when both are 3 mod 4
+        if (&a % 4u32).to_u8() == Some(3) && (&n % 4u32).to_u8() == Some(3) {
+            result = -result;
+        }
+        std::mem::swap(&mut a, &mut n);
+        a %= &n;
+    }
+    if n.is_one() {
+        result
+    } else {
+        0
+    }
+}
+
+/// The strong Lucas probable prime test, with parameters from Selfridge's method A
+///
+/// D is the first of 5, -7, 9, -11, ... with (D/n) = -1, P = 1 and Q = (1 - D) / 4. n is prime
+/// only if, with n + 1 = d * 2^s, U_d = 0 or V_(d 2^r) = 0 for some r < s. n has to be odd, and
+/// not a perfect square, or there is no such D.
+pub fn strong_lucas(n: &BigUint) -> bool {
+    let n_int = BigInt::from(n.clone());
+    let reduce = |x: BigInt| ((x % &n_int) + &n_int) % &n_int;
+    // Halving mod odd n
+    let half = |x: BigInt| {
+        let x = reduce(x);
+        let x = if x.bit(0) { x + &n_int } else { x };
+        x >> 1
     };
-    todo!()
+
+    let mut d_param = BigInt::from(5);
+    loop {
+        match jacobi(&d_param, n) {
+            -1 => break,
+            // A shared factor, unless that factor is n itself
+            0 if d_param.abs() != n_int => return false,
+            _ => {}
+        }
+        d_param = if d_param.is_positive() {
+            -(d_param + 2u32)
+        } else {
+            -d_param + 2u32
+        };
+    }
+    let p = BigInt::one();
+    let q = (BigInt::one() - &d_param) / 4u32;
+
+    let n_plus_1 = n + 1u32;
+    let s = n_plus_1.trailing_zeros().unwrap();
+    let d = &n_plus_1 >> s;
+
+    // U_1, V_1 and Q^1, then double and add along the bits of d
+    let (mut u, mut v, mut qk) = (BigInt::one(), p.clone(), reduce(q.clone()));
+    for i in (0..d.bits() - 1).rev() {
+        u = reduce(&u * &v);
+        v = reduce(&v * &v - &qk * 2u32);
+        qk = reduce(&qk * &qk);
+        if d.bit(i) {
+            let (u1, v1) = (&p * &u + &v, &d_param * &u + &p * &v);
+            u = half(u1);
+            v = half(v1);
+            qk = reduce(&qk * &q);
+        }
+    }
+    if u.is_zero() || v.is_zero() {
+        return true;
+    }
+    for _ in 1..s {
+        v = reduce(&v * &v - &qk * 2u32);
+        qk = reduce(&qk * &qk);
+        if v.is_zero() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Baillie-PSW: Miller-Rabin to base 2 followed by a strong Lucas test
+///
+/// No composite is known to pass both, and there are none below 2^64.
+pub fn baillie_psw(n: &BigUint) -> bool {
+    if let Some(prime) = trial_division(n) {
+        return prime;
+    }
+    if !strong_probable_prime(n, &BigUint::from(2u32)) {
+        return false;
+    }
+    let root = n.sqrt();
+    if &root * &root == *n {
+        return false;
+    }
+    strong_lucas(n)
+}
+
+/// Whether n is prime, by Baillie-PSW
+pub fn is_prime(n: &BigUint) -> bool {
+    baillie_psw(n)
 }
 
 #[cfg(test)]
 mod tests {
-	use super::*;
+    use super::*;
 
-	#[test]
-	fn it_works() {
-	}
+    fn big(s: &str) -> BigUint {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn small() {
+        let primes = sieve(20_000);
+        assert_eq!(&primes[..10], &[2, 3, 5, 7, 11, 13, 17, 19, 23, 29]);
+        for n in 0..20_000u32 {
+            let expected = primes.binary_search(&n).is_ok();
+            let n = BigUint::from(n);
+            assert_eq!(miller_rabin(&n, 10), expected, "{}", n);
+            assert_eq!(baillie_psw(&n), expected, "{}", n);
+        }
+    }
+
+    #[test]
+    fn pseudoprimes() {
+        // Carmichael numbers, which fool the Fermat test for every coprime base
+        for n in ["561", "41041", "825265", "321197185"] {
+            assert!(!miller_rabin(&big(n), 10));
+            assert!(!baillie_psw(&big(n)));
+        }
+        // Strong pseudoprimes to base 2, and to every base up to 23
+        for n in ["2047", "3215031751", "3825123056546413051"] {
+            assert!(strong_probable_prime(&big(n), &BigUint::from(2u32)));
+            assert!(!miller_rabin(&big(n), 0));
+            assert!(!baillie_psw(&big(n)));
+        }
+        // Strong Lucas pseudoprimes, which Miller-Rabin to base 2 catches
+        for n in ["5459", "5777", "10877", "16109", "18971"] {
+            assert!(strong_lucas(&big(n)));
+            assert!(!baillie_psw(&big(n)));
+        }
+    }
+
+    #[test]
+    fn large() {
+        let m127 = (BigUint::one() << 127) - 1u32;
+        let m521 = (BigUint::one() << 521) - 1u32;
+        let m89 = (BigUint::one() << 89) - 1u32;
+        for p in [&m127, &m521, &m89] {
+            assert!(miller_rabin(p, 20));
+            assert!(baillie_psw(p));
+            assert!(strong_lucas(p));
+        }
+        for n in [&m127 * &m89, &m521 * &m521, (BigUint::one() << 128) + 1u32] {
+            assert!(!miller_rabin(&n, 20));
+            assert!(!baillie_psw(&n));
+        }
+    }
+
+    #[test]
+    fn jacobi_symbol() {
+        // (a/7) is the Legendre symbol, and 1, 2 and 4 are the squares
+        let expected = [0, 1, 1, -1, 1, -1, -1];
+        for a in 0..7 {
+            assert_eq!(
+                jacobi(&BigInt::from(a), &BigUint::from(7u32)),
+                expected[a as usize]
+            );
+        }
+        assert_eq!(jacobi(&BigInt::from(-1), &BigUint::from(7u32)), -1);
+        assert_eq!(jacobi(&BigInt::from(1001), &BigUint::from(9907u32)), -1);
+        assert_eq!(jacobi(&BigInt::from(19), &BigUint::from(45u32)), 1);
+        assert_eq!(jacobi(&BigInt::from(3), &BigUint::from(15u32)), 0);
+    }
 }
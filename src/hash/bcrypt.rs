@@ -0,0 +1,132 @@
+//
+// bcrypt.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! bcrypt, the raw hash without the `$2b$` string encoding
+//!
+//! Keys Blowfish with EksBlowfishSetup, which rekeys 2^cost times alternating between the password
+//! and the salt, then encrypts "OrpheanBeholderScryDoubt" 64 times.
+
+use crate::cipher::blowfish::Blowfish;
+use crate::data::Bytes;
+
+/// Size of the salt, in bytes
+pub const SALT_SIZE: usize = 16;
+/// Size of the output, in bytes. The last byte of the final block is dropped
+pub const OUTPUT_SIZE: usize = 23;
+/// Passwords are null terminated, then truncated to this many bytes
+pub const MAX_KEY_SIZE: usize = 72;
+
+const MAGIC: &[u8; 24] = b"OrpheanBeholderScryDoubt";
+
+/// bcrypt's base64 alphabet, which differs from the standard one
+pub const BCRYPT_B64: &[u8; 64] =
+    b"./ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Panics if `salt` isn't 16 bytes, or `cost` isn't between 4 and 31
+pub fn bcrypt(cost: u32, salt: &[u8], password: &[u8]) -> Bytes {
+    assert!((4..=31).contains(&cost), "bcrypt cost must be from 4 to 31");
+    assert_eq!(salt.len(), SALT_SIZE, "bcrypt salt must be 16 bytes");
+    let mut key = password.to_vec();
+    key.push(0);
+    key.truncate(MAX_KEY_SIZE);
+    let state = Blowfish::eks(cost, salt, &key);
+
+    let mut words: Vec<u32> = MAGIC
+        .chunks(4)
+        .map(|w| u32::from_be_bytes([w[0], w[1], w[2], w[3]]))
+        .collect();
+    for _ in 0..64 {
+        for pair in words.chunks_mut(2) {
+            let (l, r) = state.encrypt(pair[0], pair[1]);
+            pair[0] = l;
+            pair[1] = r;
+        }
+    }
+    let output: Bytes = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    output.truncate(OUTPUT_SIZE)
+}
+
+/// Formats as `$2b$<cost>$<salt><hash>`, with the salt and hash in bcrypt's base64
+pub fn encode(cost: u32, salt: &Bytes, hash: &Bytes) -> String {
+    format!(
+        "$2b${:02}${}{}",
+        cost,
+        salt.to_64_with(BCRYPT_B64),
+        hash.to_64_with(BCRYPT_B64)
+    )
+}
+
+/// Parses the output of `encode` into the cost, salt and hash. `$2a$` and `$2y$` are accepted too,
+/// since they only differ in how they handled bugs with long or non-ASCII passwords
+pub fn decode(s: &str) -> Option<(u32, Bytes, Bytes)> {
+    let mut parts = s.strip_prefix('$')?.split('$');
+    if !matches!(parts.next()?, "2a" | "2b" | "2y") {
+        return None;
+    }
+    let cost = parts.next()?;
+    let rest = parts.next()?;
+    if cost.len() != 2 || rest.len() != 53 || parts.next().is_some() || !rest.is_ascii() {
+        return None;
+    }
+    let cost = cost.parse().ok().filter(|cost| (4..=31).contains(cost))?;
+    let salt = Bytes::read_64_with(&rest[..22], BCRYPT_B64)?;
+    let hash = Bytes::read_64_with(&rest[22..], BCRYPT_B64)?;
+    Some((cost, salt, hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        for len in 0..8 {
+            let bytes = Bytes::rand(len);
+            let encoded = bytes.to_64_with(BCRYPT_B64);
+            assert_eq!(encoded.len(), (len * 4 + 2) / 3);
+            assert_eq!(Bytes::read_64_with(&encoded, BCRYPT_B64), Some(bytes));
+        }
+        assert_eq!(Bytes::read_64_with("abcde", BCRYPT_B64), None);
+        assert_eq!(Bytes::read_64_with("ab+d", BCRYPT_B64), None);
+    }
+
+    /// From the OpenBSD and OpenWall test suites
+    #[test]
+    fn vectors() {
+        let cases = [
+            (
+                "U*U",
+                "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW",
+            ),
+            (
+                "U*U*",
+                "$2a$05$CCCCCCCCCCCCCCCCCCCCC.VGOzA784oUp/Z0DY336zx7pLYAy0lwK",
+            ),
+            (
+                "U*U*U",
+                "$2a$05$XXXXXXXXXXXXXXXXXXXXXOAcXxm9kjPGEMsLznoKqmqw7tc8WCx4a",
+            ),
+            (
+                "",
+                "$2a$05$CCCCCCCCCCCCCCCCCCCCC.7uG0VCzI2bS7j6ymqJi9CdcdxiRTWNy",
+            ),
+        ];
+        for (password, expected) in cases.iter() {
+            let (cost, salt, hash) = decode(expected).unwrap();
+            assert_eq!(bcrypt(cost, &salt, password.as_bytes()), hash);
+            assert_eq!(encode(cost, &salt, &hash), expected.replace("$2a$", "$2b$"));
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let valid = "$2b$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW";
+        assert!(decode(valid).is_some());
+        assert!(decode(&valid.replace("$2b$", "$2c$")).is_none());
+        assert!(decode(&valid.replace("$05$", "$03$")).is_none());
+        assert!(decode(&valid[..valid.len() - 1]).is_none());
+    }
+}
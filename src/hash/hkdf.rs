@@ -0,0 +1,131 @@
+//
+// hkdf.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! HKDF (RFC 5869), over any of the hashes in this module
+//!
+//! Extract concentrates the entropy of the input keying material into a pseudorandom key (PRK),
+//! then expand stretches the PRK into as many output bytes as needed:
+//!
+//! PRK = HMAC(salt, IKM)
+//! T(i) = HMAC(PRK, T(i - 1) || info || i)
+//! OKM = T(1) || T(2) || ...
+
+use super::hmac::Hmac;
+use super::Digest;
+use crate::data::Bytes;
+use std::marker::PhantomData;
+
+#[derive(Debug, Clone)]
+pub struct Hkdf<H: Digest> {
+    prk: Bytes,
+    hash: PhantomData<H>,
+}
+
+impl<H: Digest> Hkdf<H> {
+    /// Extract step. An empty salt is treated as a block of zeros
+    pub fn extract(salt: &[u8], ikm: &[u8]) -> Self {
+        let salt = if salt.is_empty() {
+            Bytes::zero(H::OUTPUT_SIZE)
+        } else {
+            Bytes::from_bytes(salt)
+        };
+        Self::from_prk(Hmac::<H>::mac(&salt, ikm))
+    }
+
+    /// Skips the extract step, when the key is already uniformly random
+    pub fn from_prk(prk: Bytes) -> Self {
+        Self {
+            prk,
+            hash: PhantomData,
+        }
+    }
+
+    pub fn prk(&self) -> &Bytes {
+        &self.prk
+    }
+
+    /// Expand step, returns `None` if more than 255 blocks of output are requested
+    pub fn expand(&self, info: &[u8], len: usize) -> Option<Bytes> {
+        if len > 255 * H::OUTPUT_SIZE {
+            return None;
+        }
+        let keyed = Hmac::<H>::new(&self.prk);
+        let mut okm = Bytes::with_capacity(len);
+        let mut t = Bytes::new();
+        let mut counter = 1u8;
+        while okm.len() < len {
+            let mut hmac = keyed.clone();
+            hmac.update(&t);
+            hmac.update(info);
+            hmac.update(&[counter]);
+            t = hmac.finalize();
+            okm += t.clone();
+            counter = counter.wrapping_add(1);
+        }
+        Some(okm.truncate(len))
+    }
+}
+
+/// Extract and expand in one go
+pub fn hkdf<H: Digest>(salt: &[u8], ikm: &[u8], info: &[u8], len: usize) -> Option<Bytes> {
+    Hkdf::<H>::extract(salt, ikm).expand(info, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::{sha1::Sha1, sha2::Sha256};
+
+    fn salt() -> Bytes {
+        (0x00..=0x0C).collect()
+    }
+
+    fn info() -> Bytes {
+        (0xF0..=0xF9).collect()
+    }
+
+    /// RFC 5869, test cases 1 and 3
+    #[test]
+    fn sha256() {
+        let ikm = Bytes::from_bytes(&[0x0B; 22]);
+        let hkdf = Hkdf::<Sha256>::extract(&salt(), &ikm);
+        assert_eq!(
+            *hkdf.prk(),
+            "077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5"
+        );
+        assert_eq!(
+            hkdf.expand(&info(), 42).unwrap(),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"
+        );
+
+        let hkdf = Hkdf::<Sha256>::extract(&[], &ikm);
+        assert_eq!(
+            *hkdf.prk(),
+            "19ef24a32c717b167f33a91d6f648bdf96596776afdb6377ac434c1c293ccb04"
+        );
+        assert_eq!(
+            hkdf.expand(&[], 42).unwrap(),
+            "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d9d201395faa4b61a96c8"
+        );
+    }
+
+    /// RFC 5869, test case 4
+    #[test]
+    fn sha1() {
+        let ikm = Bytes::from_bytes(&[0x0B; 11]);
+        assert_eq!(
+            hkdf::<Sha1>(&salt(), &ikm, &info(), 42).unwrap(),
+            "085a01ea1b10f36933068b56efa5ad81a4f14b822f5b091568a9cdd4f155fda2c22e422478d305f3f896"
+        );
+    }
+
+    #[test]
+    fn output_limit() {
+        let hkdf = Hkdf::<Sha256>::from_prk(Bytes::rand(32));
+        assert_eq!(hkdf.expand(b"", 255 * 32).unwrap().len(), 255 * 32);
+        assert!(hkdf.expand(b"", 255 * 32 + 1).is_none());
+    }
+}
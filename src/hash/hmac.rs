@@ -0,0 +1,206 @@
+//
+// hmac.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! HMAC (RFC 2104), over any of the hashes in this module
+//!
+//! HMAC(K, m) = H((K' ^ opad) || H((K' ^ ipad) || m))
+//!
+//! Where K' is the key, hashed if it is longer than a block, then padded with zeros to exactly one
+//! block.
+
+use super::Digest;
+use crate::data::Bytes;
+
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5C;
+
+/// HMAC, with the inner and outer hashers already keyed
+///
+/// Cloning a keyed `Hmac` is cheaper than re-keying, which matters for things like PBKDF2
+#[derive(Debug, Clone)]
+pub struct Hmac<H: Digest> {
+    inner: H,
+    outer: H,
+}
+
+impl<H: Digest> Hmac<H> {
+    pub fn new(key: &[u8]) -> Self {
+        let key = Self::block_key(key);
+        let mut inner = H::default();
+        inner.update(&(key.clone() ^ IPAD));
+        let mut outer = H::default();
+        outer.update(&(key ^ OPAD));
+        Self { inner, outer }
+    }
+
+    /// Hashes the key if it's longer than a block, then pads it with zeros to the block size
+    fn block_key(key: &[u8]) -> Bytes {
+        let key = if key.len() > H::BLOCK_SIZE {
+            H::digest(key)
+        } else {
+            Bytes::from_bytes(key)
+        };
+        let padding = Bytes::zero(H::BLOCK_SIZE - key.len());
+        key + padding
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finalize(self) -> Bytes {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finalize());
+        outer.finalize()
+    }
+
+    /// Checks `tag` against the MAC, without exiting early on the first wrong byte
+    pub fn verify(self, tag: &Bytes) -> bool {
+        self.finalize().ct_eq(tag)
+    }
+
+    /// Computes the MAC for `message` in one go
+    pub fn mac(key: &[u8], message: &[u8]) -> Bytes {
+        let mut hmac = Self::new(key);
+        hmac.update(message);
+        hmac.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::{md5::Md5, sha1::Sha1, sha2::*};
+
+    fn check<H: Digest>(cases: &[(Bytes, &str)], outputs: &[&str]) {
+        for ((key, message), output) in cases.iter().zip(outputs.iter()) {
+            assert_eq!(
+                Hmac::<H>::mac(key, message.as_bytes()),
+                Bytes::read_hex(output)
+            );
+        }
+    }
+
+    /// RFC 2202, test cases 2, 6 and 7 (which use the same inputs for SHA-1 and MD5)
+    fn rfc_2202() -> Vec<(Bytes, &'static str)> {
+        vec![
+            (Bytes::read_utf8("Jefe"), "what do ya want for nothing?"),
+            (
+                Bytes::from_bytes(&[0xaa; 80]),
+                "Test Using Larger Than Block-Size Key - Hash Key First",
+            ),
+            (
+                Bytes::from_bytes(&[0xaa; 80]),
+                "Test Using Larger Than Block-Size Key and Larger Than One Block-Size Data",
+            ),
+        ]
+    }
+
+    /// RFC 4231, test cases 1, 2, 6 and 7
+    fn rfc_4231() -> Vec<(Bytes, &'static str)> {
+        vec![
+            (Bytes::from_bytes(&[0x0b; 20]), "Hi There"),
+            (Bytes::read_utf8("Jefe"), "what do ya want for nothing?"),
+            (
+                Bytes::from_bytes(&[0xaa; 131]),
+                "Test Using Larger Than Block-Size Key - Hash Key First",
+            ),
+            (
+                Bytes::from_bytes(&[0xaa; 131]),
+                "This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.",
+            ),
+        ]
+    }
+
+    #[test]
+    fn hmac_sha1() {
+        check::<Sha1>(
+            &rfc_2202(),
+            &[
+                "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79",
+                "aa4ae5e15272d00e95705637ce8a3b55ed402112",
+                "e8e99d0f45237d786d6bbaa7965c7808bbff1a91",
+            ],
+        );
+    }
+
+    #[test]
+    fn hmac_md5() {
+        check::<Md5>(
+            &rfc_2202(),
+            &[
+                "750c783e6ab0b503eaa86e310a5db738",
+                "6b1ab7fe4bd7bf8f0b62e6ce61b9d0cd",
+                "6f630fad67cda0ee1fb1f562db3aa53e",
+            ],
+        );
+    }
+
+    #[test]
+    fn hmac_sha224() {
+        check::<Sha224>(
+            &rfc_4231(),
+            &[
+                "896fb1128abbdf196832107cd49df33f47b4b1169912ba4f53684b22",
+                "a30e01098bc6dbbf45690f3a7e9e6d0f8bbea2a39e6148008fd05e44",
+                "95e9a0db962095adaebe9b2d6f0dbce2d499f112f2d2b7273fa6870e",
+                "3a854166ac5d9f023f54d517d0b39dbd946770db9c2b95c9f6f565d1",
+            ],
+        );
+    }
+
+    #[test]
+    fn hmac_sha256() {
+        check::<Sha256>(
+            &rfc_4231(),
+            &[
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ],
+        );
+    }
+
+    #[test]
+    fn hmac_sha384() {
+        check::<Sha384>(
+            &rfc_4231(),
+            &[
+                "afd03944d84895626b0825f4ab46907f15f9dadbe4101ec682aa034c7cebc59cfaea9ea9076ede7f4af152e8b2fa9cb6",
+                "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e8e2240ca5e69e2c78b3239ecfab21649",
+                "4ece084485813e9088d2c63a041bc5b44f9ef1012a2b588f3cd11f05033ac4c60c2ef6ab4030fe8296248df163f44952",
+                "6617178e941f020d351e2f254e8fd32c602420feb0b8fb9adccebb82461e99c5a678cc31e799176d3860e6110c46523e",
+            ],
+        );
+    }
+
+    #[test]
+    fn hmac_sha512() {
+        check::<Sha512>(
+            &rfc_4231(),
+            &[
+                "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cdedaa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854",
+                "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+                "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f3526b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598",
+                "e37b6a775dc87dbaa4dfa9f96e5e3ffddebd71f8867289865df5a32d20cdc944b6022cac3c4982b10d5eeb55c3e4de15134676fb6de0446065c97440fa8c6a58",
+            ],
+        );
+    }
+
+    #[test]
+    fn verify() {
+        let key = Bytes::rand(16);
+        let tag = Hmac::<Sha256>::mac(&key, b"message");
+        let mut hmac = Hmac::<Sha256>::new(&key);
+        hmac.update(b"message");
+        assert!(hmac.clone().verify(&tag));
+        assert!(!hmac.clone().verify(&tag.truncate(16)));
+        let mut wrong = tag.clone();
+        wrong[31] ^= 1;
+        assert!(!hmac.verify(&wrong));
+    }
+}
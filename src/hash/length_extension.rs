@@ -0,0 +1,134 @@
+//
+// length_extension.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Length extension for Merkle–Damgård hashes
+//!
+//! A MAC of the form `H(key || message)` leaks the full internal state of the hash, so anyone can
+//! continue hashing from it. The only catch is the padding the original hash appended (the
+//! "glue"), which depends on the length of the key, and has to end up in the forged message.
+
+use std::convert::TryInto;
+use std::ops::Range;
+
+use super::Digest;
+use crate::data::Bytes;
+
+/// A hash that can be resumed from its own output
+pub trait LengthExtendable: Digest {
+    /// The padding appended to a message of `len` bytes
+    fn padding(len: u64) -> Bytes;
+
+    /// Resumes hashing from `digest`, as if `len` bytes had already been processed
+    ///
+    /// `len` must include the padding, i.e. it must be a multiple of `BLOCK_SIZE`
+    fn from_digest(digest: &Bytes, len: u64) -> Self {
+        assert_eq!(digest.len(), Self::OUTPUT_SIZE, "Digest is the wrong size");
+        assert_eq!(
+            len % Self::BLOCK_SIZE as u64,
+            0,
+            "Length must be block aligned"
+        );
+        Self::resume(digest, len)
+    }
+
+    /// Builds the hasher for `from_digest`, once the digest and length have been checked
+    fn resume(digest: &Bytes, len: u64) -> Self;
+}
+
+/// Splits a digest back into the `N` words of `W` bytes it was written from
+pub(crate) fn state_words<T: Copy + Default, const N: usize, const W: usize>(
+    digest: &[u8],
+    from_bytes: fn([u8; W]) -> T,
+) -> [T; N] {
+    let mut state = [T::default(); N];
+    for (s, b) in state.iter_mut().zip(digest.chunks_exact(W)) {
+        *s = from_bytes(b.try_into().unwrap());
+    }
+    state
+}
+
+/// Extends `original_msg` with `suffix`, assuming the key is `key_len` bytes
+///
+/// Returns the forged message (without the key), and the MAC for it
+pub fn extend<H: LengthExtendable>(
+    original_mac: &Bytes,
+    original_msg: &Bytes,
+    suffix: &Bytes,
+    key_len: usize,
+) -> (Bytes, Bytes) {
+    let original_len = (key_len + original_msg.len()) as u64;
+    let glue = H::padding(original_len);
+    let mut hasher = H::from_digest(original_mac, original_len + glue.len() as u64);
+    hasher.update(suffix);
+    let message = original_msg.clone() + glue + suffix.clone();
+    (message, hasher.finalize())
+}
+
+/// Forges a MAC for `original_msg || glue || suffix`
+///
+/// Each key length in `key_len_range` is tried until `verify` accepts the forged message and MAC.
+/// Returns the forged message and MAC, or `None` if no key length worked.
+pub fn forge<H: LengthExtendable>(
+    original_mac: &Bytes,
+    original_msg: &Bytes,
+    suffix: &Bytes,
+    key_len_range: Range<usize>,
+    verify: impl Fn(&Bytes, &Bytes) -> bool,
+) -> Option<(Bytes, Bytes)> {
+    key_len_range
+        .map(|key_len| extend::<H>(original_mac, original_msg, suffix, key_len))
+        .find(|(message, mac)| verify(message, mac))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::{
+        md4::Md4,
+        md5::Md5,
+        sha1::Sha1,
+        sha2::{Sha256, Sha512},
+    };
+
+    fn forge_with<H: LengthExtendable>() {
+        let key = Bytes::rand(13);
+        let sign = |m: &Bytes| H::digest(&(key.clone() + m.clone()));
+        let message = Bytes::read_utf8("comment1=cooking%20MCs;userdata=foo");
+        let suffix = Bytes::read_utf8(";admin=true");
+
+        let (forged, mac) = forge::<H>(&sign(&message), &message, &suffix, 0..64, |m, t| {
+            &sign(m) == t
+        })
+        .expect("No key length worked");
+        assert!(forged.ends_with(suffix.to_bytes()));
+        assert_eq!(sign(&forged), mac);
+    }
+
+    #[test]
+    fn sha1() {
+        forge_with::<Sha1>();
+    }
+
+    #[test]
+    fn md4() {
+        forge_with::<Md4>();
+    }
+
+    #[test]
+    fn md5() {
+        forge_with::<Md5>();
+    }
+
+    #[test]
+    fn sha256() {
+        forge_with::<Sha256>();
+    }
+
+    #[test]
+    fn sha512() {
+        forge_with::<Sha512>();
+    }
+}
@@ -0,0 +1,184 @@
+//
+// md4.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! MD4 (RFC 1320)
+//!
+//! Implemented here rather than using the `md4` crate, since length extension needs to be able to
+//! set the internal state directly, and resume hashing from a known digest.
+
+use super::length_extension::state_words;
+use super::{md_padding, BlockBuffer, Digest, LengthExtendable};
+use crate::data::Bytes;
+
+const INIT: [u32; 4] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476];
+
+/// MD4 hasher, with a public internal state
+#[derive(Debug, Clone)]
+pub struct Md4 {
+    /// A, B, C and D registers
+    pub state: [u32; 4],
+    buffer: BlockBuffer,
+}
+
+impl Default for Md4 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Md4 {
+    pub fn new() -> Self {
+        Self {
+            state: INIT,
+            buffer: BlockBuffer::default(),
+        }
+    }
+}
+
+impl Digest for Md4 {
+    const BLOCK_SIZE: usize = 64;
+    const OUTPUT_SIZE: usize = 16;
+
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.buffer
+            .update(data, Self::BLOCK_SIZE, |block| compress(state, block));
+    }
+
+    fn finalize(mut self) -> Bytes {
+        let padding = Self::padding(self.buffer.len());
+        self.update(&padding);
+        debug_assert!(self.buffer.is_empty());
+        self.state.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+}
+
+impl LengthExtendable for Md4 {
+    /// `0x80`, zeros, then the message length in bits as a little endian u64
+    fn padding(len: u64) -> Bytes {
+        md_padding(len, Self::BLOCK_SIZE, &len.wrapping_mul(8).to_le_bytes())
+    }
+
+    fn resume(digest: &Bytes, len: u64) -> Self {
+        Self {
+            state: state_words(digest, u32::from_le_bytes),
+            buffer: BlockBuffer::with_len(len),
+        }
+    }
+}
+
+fn f(x: u32, y: u32, z: u32) -> u32 {
+    (x & y) | (!x & z)
+}
+
+fn g(x: u32, y: u32, z: u32) -> u32 {
+    (x & y) | (x & z) | (y & z)
+}
+
+fn h(x: u32, y: u32, z: u32) -> u32 {
+    x ^ y ^ z
+}
+
+fn round1(a: u32, b: u32, c: u32, d: u32, x: u32, s: u32) -> u32 {
+    a.wrapping_add(f(b, c, d)).wrapping_add(x).rotate_left(s)
+}
+
+fn round2(a: u32, b: u32, c: u32, d: u32, x: u32, s: u32) -> u32 {
+    a.wrapping_add(g(b, c, d))
+        .wrapping_add(x)
+        .wrapping_add(0x5A82_7999)
+        .rotate_left(s)
+}
+
+fn round3(a: u32, b: u32, c: u32, d: u32, x: u32, s: u32) -> u32 {
+    a.wrapping_add(h(b, c, d))
+        .wrapping_add(x)
+        .wrapping_add(0x6ED9_EBA1)
+        .rotate_left(s)
+}
+
+/// Processes a single 64 byte block
+pub fn compress(state: &mut [u32; 4], block: &[u8]) {
+    let mut x = [0u32; 16];
+    for (x, b) in x.iter_mut().zip(block.chunks(4)) {
+        *x = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    }
+    let [mut a, mut b, mut c, mut d] = *state;
+
+    for &i in &[0, 4, 8, 12] {
+        a = round1(a, b, c, d, x[i], 3);
+        d = round1(d, a, b, c, x[i + 1], 7);
+        c = round1(c, d, a, b, x[i + 2], 11);
+        b = round1(b, c, d, a, x[i + 3], 19);
+    }
+    for i in 0..4 {
+        a = round2(a, b, c, d, x[i], 3);
+        d = round2(d, a, b, c, x[i + 4], 5);
+        c = round2(c, d, a, b, x[i + 8], 9);
+        b = round2(b, c, d, a, x[i + 12], 13);
+    }
+    for &i in &[0, 2, 1, 3] {
+        a = round3(a, b, c, d, x[i], 3);
+        d = round3(d, a, b, c, x[i + 8], 9);
+        c = round3(c, d, a, b, x[i + 4], 11);
+        b = round3(b, c, d, a, x[i + 12], 15);
+    }
+
+    state[0] = state[0].wrapping_add(a);
+    state[1] = state[1].wrapping_add(b);
+    state[2] = state[2].wrapping_add(c);
+    state[3] = state[3].wrapping_add(d);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc_1320() {
+        let vectors = [
+            ("", "31d6cfe0d16ae931b73c59d7e0c089c0"),
+            ("a", "bde52cb31de33e46245e05fbdbd6fb24"),
+            ("abc", "a448017aaf21d8525fc10ae87aa6729d"),
+            ("message digest", "d9130a8164549fe818874806e1c7014b"),
+            (
+                "abcdefghijklmnopqrstuvwxyz",
+                "d79e1c308aa5bbcdeea8ed63df412da9",
+            ),
+            (
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+                "043f8582f241db351ce627e153e7f0e4",
+            ),
+            (
+                "12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "e33b4ddc9c38f2199c3e7b164fcc0536",
+            ),
+        ];
+        for (input, output) in vectors.iter() {
+            assert_eq!(Md4::digest(input.as_bytes()), Bytes::read_hex(output));
+        }
+    }
+
+    #[test]
+    fn split_updates() {
+        let data = Bytes::rand(200);
+        let mut md4 = Md4::new();
+        for part in data.chunks(7) {
+            md4.update(part);
+        }
+        assert_eq!(md4.finalize(), Md4::digest(&data));
+    }
+
+    #[test]
+    fn resume() {
+        let message = Bytes::read_utf8("some message");
+        let padded = message.clone() + Md4::padding(message.len() as u64);
+        assert_eq!(padded.len() % Md4::BLOCK_SIZE, 0);
+        let mut resumed = Md4::from_digest(&Md4::digest(&message), padded.len() as u64);
+        resumed.update(b"more");
+        assert_eq!(resumed.finalize(), Md4::digest(&(padded + &b"more"[..])));
+    }
+}
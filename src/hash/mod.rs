@@ -0,0 +1,101 @@
+//
+// mod.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Hash functions, with their internal state exposed for attacks
+
+pub mod bcrypt;
+pub mod hkdf;
+pub mod hmac;
+pub mod length_extension;
+pub mod md4;
+pub mod md5;
+pub mod pbkdf2;
+pub mod scrypt;
+pub mod sha1;
+pub mod sha2;
+
+pub use length_extension::LengthExtendable;
+
+use crate::data::Bytes;
+
+/// Common interface for the hash functions in this module
+pub trait Digest: Clone + Default {
+    /// Size of the blocks the compression function operates on, in bytes
+    const BLOCK_SIZE: usize;
+    /// Size of the final digest, in bytes
+    const OUTPUT_SIZE: usize;
+
+    fn update(&mut self, data: &[u8]);
+
+    fn finalize(self) -> Bytes;
+
+    /// Hashes `data` in one go
+    fn digest(data: &[u8]) -> Bytes {
+        let mut hasher = Self::default();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// Buffers input until there is a full block to pass to the compression function
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BlockBuffer {
+    buffer: Vec<u8>,
+    len: u64,
+}
+
+impl BlockBuffer {
+    /// Creates a buffer that has already processed `len` bytes
+    pub(crate) fn with_len(len: u64) -> Self {
+        Self {
+            buffer: Vec::new(),
+            len,
+        }
+    }
+
+    /// Total number of bytes written
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub(crate) fn update(
+        &mut self,
+        data: &[u8],
+        block_size: usize,
+        mut compress: impl FnMut(&[u8]),
+    ) {
+        self.len += data.len() as u64;
+        let mut data = data;
+        if !self.buffer.is_empty() {
+            let take = (block_size - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() == block_size {
+                compress(&self.buffer);
+                self.buffer.clear();
+            }
+        }
+        let mut blocks = data.chunks_exact(block_size);
+        for block in &mut blocks {
+            compress(block);
+        }
+        self.buffer.extend_from_slice(blocks.remainder());
+    }
+}
+
+/// Merkle–Damgård padding for a message of `len` bytes
+///
+/// `0x80`, then zeros, then `length` (the encoded bit length), so that the total is a multiple of
+/// `block_size`
+pub(crate) fn md_padding(len: u64, block_size: usize, length: &[u8]) -> Bytes {
+    let used = (len % block_size as u64) as usize + 1 + length.len();
+    let zeros = (block_size - used % block_size) % block_size;
+    (Bytes::from_bytes(&[0x80]) + Bytes::zero(zeros)) + length
+}
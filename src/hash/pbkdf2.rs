@@ -0,0 +1,114 @@
+//
+// pbkdf2.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! PBKDF2 (RFC 8018), with HMAC over any of the hashes in this module as the PRF
+//!
+//! Each block of output is U_1 ^ U_2 ^ ... ^ U_c, where U_1 = PRF(P, S || i) and
+//! U_j = PRF(P, U_{j-1}). The iteration count c is what makes guessing passwords expensive.
+
+use super::hmac::Hmac;
+use super::Digest;
+use crate::data::Bytes;
+
+/// Derives `len` bytes from `password` and `salt`
+///
+/// Panics if `iterations` is zero
+pub fn pbkdf2<H: Digest>(password: &[u8], salt: &[u8], iterations: u32, len: usize) -> Bytes {
+    assert!(iterations > 0, "PBKDF2 needs at least one iteration");
+    // Keying HMAC is the same for every call, so it's only done once
+    let keyed = Hmac::<H>::new(password);
+    let prf = |data: &[u8]| {
+        let mut hmac = keyed.clone();
+        hmac.update(data);
+        hmac.finalize()
+    };
+    let mut ret = Bytes::with_capacity(len);
+    let mut index = 1u32;
+    while ret.len() < len {
+        let mut u = prf(&(Bytes::from_bytes(salt) + Bytes::from_bytes(&index.to_be_bytes())));
+        let mut block = u.clone();
+        for _ in 1..iterations {
+            u = prf(&u);
+            block ^= &u;
+        }
+        ret += block;
+        index += 1;
+    }
+    ret.truncate(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::{sha1::Sha1, sha2::Sha256};
+
+    /// RFC 6070
+    #[test]
+    fn sha1() {
+        let cases: [(&[u8], &[u8], u32, &str); 5] = [
+            (
+                b"password",
+                b"salt",
+                1,
+                "0c60c80f961f0e71f3a9b524af6012062fe037a6",
+            ),
+            (
+                b"password",
+                b"salt",
+                2,
+                "ea6c014dc72d6f8ccd1ed92ace1d41f0d8de8957",
+            ),
+            (
+                b"password",
+                b"salt",
+                4096,
+                "4b007901b765489abead49d926f721d065a429c1",
+            ),
+            (
+                b"passwordPASSWORDpassword",
+                b"saltSALTsaltSALTsaltSALTsaltSALTsalt",
+                4096,
+                "3d2eec4fe41c849b80c8d83662c0e44a8b291a964cf2f07038",
+            ),
+            (
+                b"pass\0word",
+                b"sa\0lt",
+                4096,
+                "56fa6aa75548099dcc37d7f03425e0c3",
+            ),
+        ];
+        for (password, salt, iterations, output) in cases.iter() {
+            assert_eq!(
+                pbkdf2::<Sha1>(password, salt, *iterations, output.len() / 2),
+                *output
+            );
+        }
+    }
+
+    #[test]
+    fn sha256() {
+        let cases = [
+            (
+                1,
+                "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b",
+            ),
+            (
+                2,
+                "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43",
+            ),
+            (
+                4096,
+                "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a",
+            ),
+        ];
+        for (iterations, output) in cases.iter() {
+            assert_eq!(
+                pbkdf2::<Sha256>(b"password", b"salt", *iterations, 32),
+                *output
+            );
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//
+// scrypt.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! scrypt (RFC 7914)
+//!
+//! PBKDF2-HMAC-SHA256 spreads the password over `p` blocks of 128 * `r` bytes, each of which is
+//! run through ROMix - filling a table of N = 2^`log_n` blocks, then reading it back in a data
+//! dependent order - before a final PBKDF2 pass. The table is what makes it memory hard.
+
+use super::pbkdf2::pbkdf2;
+use super::sha2::Sha256;
+use crate::data::Bytes;
+
+/// Salsa20/8 core, applied to a block of 16 little endian words in place
+pub fn salsa20_8(block: &mut [u32; 16]) {
+    let mut x = *block;
+    let mut quarter = |a: usize, b: usize, c: usize, d: usize| {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    };
+    for _ in 0..4 {
+        // Columns
+        quarter(0, 4, 8, 12);
+        quarter(5, 9, 13, 1);
+        quarter(10, 14, 2, 6);
+        quarter(15, 3, 7, 11);
+        // Rows
+        quarter(0, 1, 2, 3);
+        quarter(5, 6, 7, 4);
+        quarter(10, 11, 8, 9);
+        quarter(15, 12, 13, 14);
+    }
+    for (b, x) in block.iter_mut().zip(x.iter()) {
+        *b = b.wrapping_add(*x);
+    }
+}
+
+/// BlockMix over 2 * `r` 64 byte blocks. Even outputs go in the first half, odd in the second
+fn block_mix(input: &[u32], r: usize) -> Vec<u32> {
+    let mut output = vec![0u32; input.len()];
+    let mut x = [0u32; 16];
+    x.copy_from_slice(&input[input.len() - 16..]);
+    for (i, block) in input.chunks(16).enumerate() {
+        for (x, b) in x.iter_mut().zip(block.iter()) {
+            *x ^= b;
+        }
+        salsa20_8(&mut x);
+        let offset = (i / 2 + (i % 2) * r) * 16;
+        output[offset..offset + 16].copy_from_slice(&x);
+    }
+    output
+}
+
+fn ro_mix(block: &mut Vec<u32>, n: usize, r: usize) {
+    let mut table = Vec::with_capacity(n);
+    for _ in 0..n {
+        let next = block_mix(block, r);
+        table.push(std::mem::replace(block, next));
+    }
+    for _ in 0..n {
+        // Integerify, the first word of the last 64 byte block
+        let j = block[(2 * r - 1) * 16] as usize % n;
+        for (b, v) in block.iter_mut().zip(table[j].iter()) {
+            *b ^= v;
+        }
+        *block = block_mix(block, r);
+    }
+}
+
+/// Derives `len` bytes from `password` and `salt`, with a cost of N = 2^`log_n`
+///
+/// Panics if `log_n` is zero, or `r` or `p` are zero
+pub fn scrypt(password: &[u8], salt: &[u8], log_n: u8, r: u32, p: u32, len: usize) -> Bytes {
+    assert!(log_n > 0 && r > 0 && p > 0, "Invalid scrypt parameters");
+    let (n, r) = (1usize << log_n, r as usize);
+    let b = pbkdf2::<Sha256>(password, salt, 1, p as usize * 128 * r);
+    let mut mixed = Bytes::with_capacity(b.len());
+    for chunk in b.chunks(128 * r) {
+        let mut block: Vec<u32> = chunk
+            .chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+        ro_mix(&mut block, n, r);
+        for word in block {
+            mixed += &word.to_le_bytes()[..];
+        }
+    }
+    pbkdf2::<Sha256>(password, &mixed, 1, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 7914, section 8
+    #[test]
+    fn salsa() {
+        let input = Bytes::read_hex(concat!(
+            "7e879a214f3ec9867ca940e641718f26baee555b8c61c1b50df846116dcd3b1d",
+            "ee24f319df9b3d8514121e4b5ac5aa3276021d2909c74829edebc68db8b8c25e",
+        ));
+        let mut block = [0u32; 16];
+        for (w, b) in block.iter_mut().zip(input.chunks(4)) {
+            *w = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        }
+        salsa20_8(&mut block);
+        let output: Bytes = block.iter().flat_map(|w| w.to_le_bytes()).collect();
+        assert_eq!(
+            output,
+            concat!(
+                "a41f859c6608cc993b81cacb020cef05044b2181a2fd337dfd7b1c6396682f29",
+                "b4393168e3c9e6bcfe6bc5b7a06d96bae424cc102c91745c24ad673dc7618f81",
+            )
+        );
+    }
+
+    /// RFC 7914, section 12, the first two cases
+    #[test]
+    fn vectors() {
+        assert_eq!(
+            scrypt(b"", b"", 4, 1, 1, 64),
+            concat!(
+                "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442",
+                "fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906",
+            )
+        );
+        assert_eq!(
+            scrypt(b"password", b"NaCl", 10, 8, 16, 64),
+            concat!(
+                "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162",
+                "2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640",
+            )
+        );
+    }
+}
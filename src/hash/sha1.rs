@@ -0,0 +1,140 @@
+//
+// sha1.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! SHA-1 (FIPS 180-4)
+
+use super::length_extension::state_words;
+use super::{md_padding, BlockBuffer, Digest, LengthExtendable};
+use crate::data::Bytes;
+
+const INIT: [u32; 5] = [
+    0x6745_2301,
+    0xEFCD_AB89,
+    0x98BA_DCFE,
+    0x1032_5476,
+    0xC3D2_E1F0,
+];
+
+/// SHA-1 hasher, with a public internal state
+#[derive(Debug, Clone)]
+pub struct Sha1 {
+    /// H0 through H4
+    pub state: [u32; 5],
+    buffer: BlockBuffer,
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha1 {
+    pub fn new() -> Self {
+        Self {
+            state: INIT,
+            buffer: BlockBuffer::default(),
+        }
+    }
+}
+
+impl Digest for Sha1 {
+    const BLOCK_SIZE: usize = 64;
+    const OUTPUT_SIZE: usize = 20;
+
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.buffer
+            .update(data, Self::BLOCK_SIZE, |block| compress(state, block));
+    }
+
+    fn finalize(mut self) -> Bytes {
+        let padding = Self::padding(self.buffer.len());
+        self.update(&padding);
+        debug_assert!(self.buffer.is_empty());
+        self.state.iter().flat_map(|s| s.to_be_bytes()).collect()
+    }
+}
+
+impl LengthExtendable for Sha1 {
+    /// `0x80`, zeros, then the message length in bits as a big endian u64
+    fn padding(len: u64) -> Bytes {
+        md_padding(len, Self::BLOCK_SIZE, &len.wrapping_mul(8).to_be_bytes())
+    }
+
+    fn resume(digest: &Bytes, len: u64) -> Self {
+        Self {
+            state: state_words(digest, u32::from_be_bytes),
+            buffer: BlockBuffer::with_len(len),
+        }
+    }
+}
+
+/// Processes a single 64 byte block
+pub fn compress(state: &mut [u32; 5], block: &[u8]) {
+    let mut w = [0u32; 80];
+    for (w, b) in w.iter_mut().zip(block.chunks(4)) {
+        *w = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    }
+    for t in 16..80 {
+        w[t] = (w[t - 3] ^ w[t - 8] ^ w[t - 14] ^ w[t - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (t, w) in w.iter().enumerate() {
+        let (f, k) = match t {
+            0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+            20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+            _ => (b ^ c ^ d, 0xCA62_C1D6),
+        };
+        let temp = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(*w);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+
+    state[0] = state[0].wrapping_add(a);
+    state[1] = state[1].wrapping_add(b);
+    state[2] = state[2].wrapping_add(c);
+    state[3] = state[3].wrapping_add(d);
+    state[4] = state[4].wrapping_add(e);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fips_180() {
+        let vectors = [
+            ("", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            ("abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (
+                "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            ),
+        ];
+        for (input, output) in vectors.iter() {
+            assert_eq!(Sha1::digest(input.as_bytes()), Bytes::read_hex(output));
+        }
+        let mut sha1 = Sha1::new();
+        for _ in 0..1000 {
+            sha1.update(&[b'a'; 1000]);
+        }
+        assert_eq!(
+            sha1.finalize(),
+            Bytes::read_hex("34aa973cd4c4daa4f61eeb2bdbad27316534016f")
+        );
+    }
+}
@@ -7,12 +7,15 @@
 pub mod cipher;
 pub mod data;
 pub mod decrypt;
+pub mod dictionary;
 pub mod file;
+pub mod hash;
 pub mod keys;
 pub mod lang;
 pub mod oracle;
 pub mod random;
-mod fermat;
+pub mod timing;
+pub mod fermat;
 
 pub use data::Bytes;
 pub use file::File;
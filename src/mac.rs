@@ -5,14 +5,13 @@
 //
 
 use crate::data::Bytes;
-use md4::Digest;
-use sha::sha1::Sha1;
-use sha::utils::DigestExt;
-use std::io::Write;
+use crate::hash::{hmac::Hmac, md4::Md4, md5::Md5, sha1::Sha1, sha2::Sha256, Digest};
 
 enum Algo {
     Sha1,
+    Sha256,
     Md4,
+    Md5,
 }
 
 pub struct SecrectDigest {
@@ -27,53 +26,44 @@ impl SecrectDigest {
             algo: Algo::Sha1,
         }
     }
+    pub fn sha256() -> Self {
+        Self {
+            key: Bytes::rand(16),
+            algo: Algo::Sha256,
+        }
+    }
     pub fn md4() -> Self {
         Self {
-            key: Bytes::rand(0),
+            key: Bytes::rand(16),
             algo: Algo::Md4,
         }
     }
+    pub fn md5() -> Self {
+        Self {
+            key: Bytes::rand(16),
+            algo: Algo::Md5,
+        }
+    }
     pub fn len(&self) -> usize {
         self.key.len()
     }
     pub fn sign(&self, message: &Bytes) -> Bytes {
         match self.algo {
-            Algo::Sha1 => {
-                let mut sha1 = Sha1::default();
-                sha1.write_all(self.key.to_bytes()).unwrap();
-                sha1.write_all(message.to_bytes()).unwrap();
-                sha1.flush().unwrap();
-                Bytes::from_vec(sha1.to_bytes())
-            }
-            Algo::Md4 => {
-                let mut md4 = md4::Md4::new();
-                md4.update(self.key.to_bytes());
-                md4.update(message.to_bytes());
-                unsafe {
-                    let mut md4: md4::Md4 = std::mem::transmute_copy(&md4);
-                    let mut gen_arr: () = Default::default();
-                    //use digest::FixedOutputDirty;
-                    //md4.finalize_into_dirty(&mut gen_arr);
-                    let raw: [u32; 24] = std::mem::transmute(md4);
-                    println!("{:X} => {:X?}", message, &raw[20..]);
-                    println!("len: {:X?}", &raw[0..2]);
-                }
-                Bytes::from_bytes(&md4.finalize()[..])
-            }
+            Algo::Sha1 => self.sign_with::<Sha1>(message),
+            Algo::Sha256 => self.sign_with::<Sha256>(message),
+            Algo::Md4 => self.sign_with::<Md4>(message),
+            Algo::Md5 => self.sign_with::<Md5>(message),
         }
     }
-    pub fn verify(&self, message: &Bytes, mac: &Bytes) -> bool {
-        self.sign(message) == mac
+    fn sign_with<H: Digest>(&self, message: &Bytes) -> Bytes {
+        let mut hasher = H::default();
+        hasher.update(self.key.to_bytes());
+        hasher.update(message.to_bytes());
+        hasher.finalize()
     }
-}
-
-fn sha1(parts: &[&[u8]]) -> Vec<u8> {
-    let mut sha1 = Sha1::default();
-    for part in parts {
-        sha1.write_all(part).unwrap();
+    pub fn verify(&self, message: &Bytes, mac: &Bytes) -> bool {
+        self.sign(message).ct_eq(mac)
     }
-    sha1.flush().unwrap();
-    sha1.to_bytes()
 }
 
 pub struct HMAC {
@@ -84,12 +74,7 @@ pub struct HMAC {
 impl HMAC {
     pub fn init(millis: u64) -> Self {
         Self {
-            key: {
-                let mut sha1 = Sha1::default();
-                sha1.write_all(Bytes::rand(100).to_bytes()).unwrap();
-                sha1.flush().unwrap();
-                Bytes::from_vec(sha1.to_bytes())
-            },
+            key: Bytes::rand(16),
             millis,
         }
     }
@@ -99,36 +84,32 @@ impl HMAC {
     pub fn get_millis(&self) -> u64 {
         self.millis
     }
-    pub fn verify(&self, file: impl AsRef<str>, signature: &Bytes) -> bool {
-        self.test(file, signature, 0)
-    }
-    pub fn weak_verify(&self, file: impl AsRef<str>, signature: &Bytes) -> bool {
-        self.test(file, signature, self.millis)
+    pub fn verify(&self, message: &[u8], signature: &Bytes) -> bool {
+        let mut hmac = Hmac::<Sha1>::new(&self.key);
+        hmac.update(message);
+        hmac.verify(signature)
     }
-    fn test(&self, file: impl AsRef<str>, signature: &Bytes, millis: u64) -> bool {
-        if signature.len() != Sha1::default_len() {
+    /// Insecure compare, which exits on the first incorrect byte, and sleeps `millis` after
+    /// each correct byte
+    pub fn weak_verify(&self, message: &[u8], signature: &Bytes) -> bool {
+        if signature.len() != Sha1::OUTPUT_SIZE {
             false
         } else {
-            for (a, b) in self.sign(file).iter().zip(signature.iter()) {
+            for (a, b) in self.sign(message).iter().zip(signature.iter()) {
                 if a != b {
                     return false;
                 }
-                std::thread::sleep(std::time::Duration::from_millis(millis));
+                std::thread::sleep(std::time::Duration::from_millis(self.millis));
             }
             true
         }
     }
-    pub fn sign(&self, file: impl AsRef<str>) -> Vec<u8> {
-        // self.key is already hashed
-        let o_key_pad = self.key.clone() ^ 0x5Cu8;
-        let i_key_pad = self.key.clone() ^ 0x36u8;
-        sha1(&[
-            o_key_pad.to_bytes(),
-            &sha1(&[i_key_pad.to_bytes(), file.as_ref().as_bytes()]),
-        ])
+    pub fn sign(&self, message: &[u8]) -> Bytes {
+        Hmac::<Sha1>::mac(&self.key, message)
     }
 }
 
+/// Early exit compare, kept for measuring timing leaks - `Bytes::ct_eq` is the safe version
 pub fn weak_compare(a: &Bytes, b: &Bytes) -> bool {
     if a.len() != b.len() {
         false
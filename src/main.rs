@@ -6,27 +6,29 @@ extern crate lazy_static;
 mod cipher;
 mod data;
 mod decrypt;
+mod dh;
+mod dictionary;
+mod fermat;
 mod file;
+mod hash;
 mod keys;
 mod lang;
 mod mac;
 mod oracle;
 mod random;
+mod srp;
+mod timing;
 
 mod comms;
 mod passwd;
 
-use block_buffer::Eager;
-use cryptopals::cipher::diffie::diffie_hellman_a;
-use num_bigint::BigUint;
+use num_bigint::{BigUint, RandBigInt};
 use oracle::Oracle;
 use rand::random;
-use sha::sha256;
-use std::hash::Hasher;
+use hash::sha2::Sha256;
 use std::iter::FromIterator;
 use std::sync::mpsc::*;
 
-use crate::passwd::PasswdStore;
 #[cfg(test)]
 use crate::cipher::BLOCK_SIZE;
 
@@ -60,263 +62,192 @@ fn challenge_5_39() {
     // RSA
 }
 
+#[test]
 fn challenge_5_38() {
     // Offline dictionary attack
     //
     // From my understanding, by mitm-ing the connection, it is possible to get your hands on
     // enough information to make an offline attack on the hash, while also allowing the client
     // to still authenticate with the server
-}
-
-fn challenge_5_37() {
-    // SRP - Secure Remote Protocol
+    use srp::simplified;
     enum Message {
         SendD { email: String, d_a: BigUint },
-        SendSalt { salt: data::Bytes, d_b: BigUint },
-        SendHMAC { sig: Vec<u8> },
-        Complete { res: bool },
+        SendSalt {
+            salt: data::Bytes,
+            d_b: BigUint,
+            u: BigUint,
+        },
+        SendHMAC { sig: data::Bytes },
     }
 
-    let mut store = PasswdStore::new();
-    store.add_user("admin@me.net".to_string(), "admin");
-    let client = |tx: Sender<Message>, rx: Receiver<Message>| {
-        let (m_a, d_a) = diffie_hellman_a();
-        println!("Sending SendD");
+    let words = std::fs::read_to_string("wordlist").unwrap();
+    let words: Vec<_> = words.lines().collect();
+    let passwd = words[random::<usize>() % words.len()].to_string();
+    println!("Client password: {}", passwd);
+    let expected = passwd.clone();
+    let srp = srp::Srp::<Sha256>::new(srp::RFC5054_1536.clone());
+    let client_srp = srp.clone();
+    let client = move |tx: Sender<Message>, rx: Receiver<Message>| {
+        let m_a = rand::thread_rng().gen_biguint(256);
+        let d_a = client_srp.group.g.modpow(&m_a, &client_srp.group.n);
         tx.send(Message::SendD {
             email: "admin@me.net".to_string(),
-            d_a: d_a.clone(),
+            d_a,
         })
         .unwrap();
-        println!("Waiting on Salt");
-        if let Message::SendSalt { salt, d_b } = rx.recv().unwrap() {
-            println!("Recieved Salt");
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&d_a.to_bytes_le());
-            hasher.write(&d_b.to_bytes_le());
-            let u = BigUint::from(hasher.finish().to_le());
-
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&salt);
-            hasher.write("admin".as_bytes());
-            let x = BigUint::from(hasher.finish().to_le());
-
-            let t =
-                BigUint::from(3usize) * cipher::diffie::NIST_G.modpow(&x, &*cipher::diffie::NIST_P);
-
-            let s = (&d_b - &t).modpow(&(m_a + (&u * &x)), &*cipher::diffie::NIST_P);
-
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&s.to_bytes_le());
-            let k_ = hasher.finish().to_le();
-            let mac = mac::HMAC::key(salt);
-            let sig = mac.sign(format!("{}", k_));
-            println!("Sending HMAC");
+        if let Message::SendSalt { salt, d_b, u } = rx.recv().unwrap() {
+            // Simplified SRP: B doesn't depend on the password, so the client can't tell that
+            // it's talking to someone who doesn't know the verifier
+            let sig = simplified::client_proof(
+                &client_srp,
+                "admin@me.net",
+                &passwd,
+                &m_a,
+                &salt,
+                &d_b,
+                &u,
+            );
             tx.send(Message::SendHMAC { sig }).unwrap();
-            println!("Waiting on Complete");
-            if let Message::Complete { res } = rx.recv().unwrap() {
-                if !res {
-                    panic!("Failed to authenticate");
-                }
-                println!("Result: {}", res);
-            }
         }
     };
-    let server = move |tx: Sender<Message>, rx: Receiver<Message>| {
-        println!("Waiting on SendD");
+    let attacker = move |tx: Sender<Message>, rx: Receiver<Message>| {
         if let Message::SendD { email, d_a } = rx.recv().unwrap() {
-            println!("Recieved SendD");
-            let tmp = store.db.get(&email).unwrap();
-            let (m_b, d_b_old) = diffie_hellman_a();
-            let x = BigUint::from_bytes_le(tmp.hash.to_bytes()); // Same hash
-            let v = cipher::diffie::NIST_G.modpow(&x, &*cipher::diffie::NIST_P);
-            let d_b = d_b_old.clone() + (v.clone() * BigUint::from(3usize));
-            println!("Sending Salt");
+            // b = 1 and u = 1, so S = (A * v^u)^b = A * g^x
+            let (b, u) = (BigUint::from(1usize), BigUint::from(1usize));
+            let salt = data::Bytes::new();
             tx.send(Message::SendSalt {
-                salt: tmp.salt.clone(),
-                d_b: d_b.clone(),
+                salt: salt.clone(),
+                d_b: srp.group.g.clone(),
+                u: u.clone(),
             })
             .unwrap();
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&d_a.to_bytes_le());
-            hasher.write(&d_b.to_bytes_le());
-            let u = BigUint::from(hasher.finish().to_le());
-
-            let s = (d_a * v.clone().modpow(&u, &*cipher::diffie::NIST_P))
-                .modpow(&m_b, &*cipher::diffie::NIST_P);
-            println!("s: {}", s);
-
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&s.to_bytes_le());
-            let k_ = hasher.finish().to_le();
-            println!("Waiting on HMAC");
             if let Message::SendHMAC { sig } = rx.recv().unwrap() {
-                let mac = mac::HMAC::key(tmp.salt.clone());
-                let res = mac.verify(format!("{}", k_), &data::Bytes::from_vec(sig));
-                println!("Sending Complete");
-                tx.send(Message::Complete { res }).unwrap();
-            }
-        }
-    };
-    // Verify the server works - Can't due to lifetimes
-    //comms::comm_channel(client, server);
-    let attacker = |tx: Sender<Message>, rx: Receiver<Message>| {
-        let (m_a, d_a) = diffie_hellman_a();
-        println!("Sending SendD");
-        tx.send(Message::SendD {
-            email: "admin@me.net".to_string(),
-            d_a: cipher::diffie::NIST_P.clone() * BigUint::from(1usize),
-            // s = 0 (regaurdless of the size of the multiplier)
-            //d_a: 0usize.into(),
-            // s = 0
-            //d_a: d_a.clone(),
-            // Requires actual password
-        })
-        .unwrap();
-        println!("Waiting on Salt");
-        if let Message::SendSalt { salt, d_b } = rx.recv().unwrap() {
-            println!("Recieved Salt");
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&d_a.to_bytes_le());
-            hasher.write(&d_b.to_bytes_le());
-            let u = BigUint::from(hasher.finish().to_le());
-
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&salt);
-            hasher.write("admin".as_bytes());
-            let x = BigUint::from(hasher.finish().to_le());
-
-            let t =
-                BigUint::from(3usize) * cipher::diffie::NIST_G.modpow(&x, &*cipher::diffie::NIST_P);
-
-            let s = (&d_b - &t).modpow(&(m_a + (&u * &x)), &*cipher::diffie::NIST_P);
-            let s: BigUint = 0usize.into();
-
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&s.to_bytes_le());
-            let k_ = hasher.finish().to_le();
-            let mac = mac::HMAC::key(salt);
-            let sig = mac.sign(format!("{}", k_));
-            println!("Sending HMAC");
-            tx.send(Message::SendHMAC { sig }).unwrap();
-            println!("Waiting on Complete");
-            if let Message::Complete { res } = rx.recv().unwrap() {
-                if !res {
-                    panic!("Failed to authenticate");
-                }
-                println!("Result: {}", res);
+                let dictionary = dictionary::Dictionary::from_file("wordlist");
+                let cracked =
+                    simplified::crack(&srp, &dictionary, &email, &d_a, &salt, &b, &u, &sig)
+                        .unwrap();
+                println!(
+                    "Cracked: {:?}, {} candidates in {}ms ({:.0}/s)",
+                    cracked.password,
+                    cracked.candidates,
+                    cracked.elapsed.as_millis(),
+                    cracked.throughput()
+                );
+                assert_eq!(cracked.password, Some(expected.clone()));
             }
         }
     };
-    comms::comm_channel(attacker, server);
+    comms::comm_channel(client, attacker);
 }
 
-//#[test]
-fn challenge_5_36() {
+#[test]
+fn challenge_5_37() {
     // SRP - Secure Remote Protocol
-    enum Message {
-        SendD { email: String, d_a: BigUint },
-        SendSalt { salt: data::Bytes, d_b: BigUint },
-        SendHMAC { sig: Vec<u8> },
-        Complete { res: bool },
+    // Break SRP with a zero key: A = 0, N, 2N all make the server's S = 0
+    let srp = srp::Srp::<Sha256>::new(srp::RFC5054_1536.clone());
+    let mut server = srp::Server::new(srp.clone()).insecure();
+    server.add_user("admin@me.net", "admin");
+    for multiple in 0..3 {
+        let (results, collected) = channel();
+        let attacker = srp.clone();
+        let server = server.clone();
+        comms::comm_channel(
+            move |tx, rx| {
+                let mut channel = comms::transport::Channel::new(tx, rx);
+                let key = srp::zero_key_login(&attacker, "admin@me.net", multiple, &mut channel);
+                results.send(key).unwrap();
+            },
+            move |tx, rx| {
+                let mut channel = comms::transport::Channel::new(tx, rx);
+                let (user, _) = server.clone().run(&mut channel).unwrap();
+                println!("Logged in as {} with A = {}N", user, multiple);
+            },
+        );
+        assert!(collected.recv().unwrap().is_ok());
     }
+}
 
-    let mut store = PasswdStore::new();
-    store.add_user("admin@me.net".to_string(), "admin");
-    let client = |tx: Sender<Message>, rx: Receiver<Message>| {
-        let (m_a, d_a) = diffie_hellman_a();
-        println!("Sending SendD");
-        tx.send(Message::SendD {
-            email: "admin@me.net".to_string(),
-            d_a: d_a.clone(),
-        })
-        .unwrap();
-        println!("Waiting on Salt");
-        if let Message::SendSalt { salt, d_b } = rx.recv().unwrap() {
-            println!("Recieved Salt");
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&d_a.to_bytes_le());
-            hasher.write(&d_b.to_bytes_le());
-            let u = BigUint::from(hasher.finish().to_le());
-
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&salt);
-            hasher.write("admin".as_bytes());
-            let x = BigUint::from(hasher.finish().to_le());
-
-            let t =
-                BigUint::from(3usize) * cipher::diffie::NIST_G.modpow(&x, &*cipher::diffie::NIST_P);
-
-            let s = (&d_b - &t).modpow(&(m_a + (&u * &x)), &*cipher::diffie::NIST_P);
-
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&s.to_bytes_le());
-            let k_ = hasher.finish().to_le();
-            let mac = mac::HMAC::key(salt);
-            let sig = mac.sign(format!("{}", k_));
-            println!("Sending HMAC");
-            tx.send(Message::SendHMAC { sig }).unwrap();
-            println!("Waiting on Complete");
-            if let Message::Complete { res } = rx.recv().unwrap() {
-                if !res {
-                    panic!("Failed to authenticate");
-                }
-                println!("Result: {}", res);
-            }
-        }
-    };
-    let server = move |tx: Sender<Message>, rx: Receiver<Message>| {
-        println!("Waiting on SendD");
-        if let Message::SendD { email, d_a } = rx.recv().unwrap() {
-            println!("Recieved SendD");
-            let tmp = store.db.get(&email).unwrap();
-            let (m_b, d_b_old) = diffie_hellman_a();
-            let x = BigUint::from_bytes_le(tmp.hash.to_bytes()); // Same hash
-            let v = cipher::diffie::NIST_G.modpow(&x, &*cipher::diffie::NIST_P);
-            let d_b = d_b_old.clone() + (v.clone() * BigUint::from(3usize));
-            println!("Sending Salt");
-            tx.send(Message::SendSalt {
-                salt: tmp.salt.clone(),
-                d_b: d_b.clone(),
-            })
-            .unwrap();
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&d_a.to_bytes_le());
-            hasher.write(&d_b.to_bytes_le());
-            let u = BigUint::from(hasher.finish().to_le());
-
-            let s = (d_a * v.clone().modpow(&u, &*cipher::diffie::NIST_P))
-                .modpow(&m_b, &*cipher::diffie::NIST_P);
-
-            let mut hasher = sha256::Sha256::default();
-            hasher.write(&s.to_bytes_le());
-            let k_ = hasher.finish().to_le();
-            println!("Waiting on HMAC");
-            if let Message::SendHMAC { sig } = rx.recv().unwrap() {
-                let mac = mac::HMAC::key(tmp.salt.clone());
-                let res = mac.verify(format!("{}", k_), &data::Bytes::from_vec(sig));
-                println!("Sending Complete");
-                tx.send(Message::Complete { res }).unwrap();
-            }
-        }
-    };
-    comms::comm_channel(client, server);
+#[test]
+fn challenge_5_36() {
+    // SRP - Secure Remote Protocol
+    let srp = srp::Srp::<Sha256>::new(srp::RFC5054_1536.clone());
+    let mut server = srp::Server::new(srp.clone());
+    server.add_user("admin@me.net", "admin");
+    let client = srp::Client::new(srp, "admin@me.net", "admin");
+    let (results, collected) = channel();
+    let server_results = results.clone();
+    comms::comm_channel(
+        move |tx, rx| {
+            let mut channel = comms::transport::Channel::new(tx, rx);
+            results.send(client.clone().run(&mut channel).unwrap()).unwrap();
+        },
+        move |tx, rx| {
+            let mut channel = comms::transport::Channel::new(tx, rx);
+            let (_, key) = server.clone().run(&mut channel).unwrap();
+            server_results.send(key).unwrap();
+        },
+    );
+    let keys: Vec<_> = collected.iter().collect();
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys[0], keys[1]);
+}
+
+/// Runs the negotiated group echo through a proxy replacing g, returning whether each side
+/// finished, and what the proxy read
+fn malicious_g(
+    substitution: dh::malicious_g::Substitution,
+    client: dh::Client,
+    server: dh::Server,
+) -> (bool, bool, Vec<data::Bytes>) {
+    let (mitm, plaintexts) = dh::malicious_g::attack(substitution);
+    let (results, collected) = channel();
+    let server_results = results.clone();
+    mitm.run(
+        move |tx, rx| {
+            let message = data::Bytes::read_utf8("Negotiated groups are only as good as g");
+            let result = client.run(&mut comms::transport::Channel::new(tx, rx), &message);
+            results.send((0, result.is_ok())).unwrap();
+        },
+        move |tx, rx| {
+            let result = server.run(&mut comms::transport::Channel::new(tx, rx));
+            server_results.send((1, result.is_ok())).unwrap();
+        },
+    );
+    let mut finished: Vec<(u8, bool)> = collected.iter().collect();
+    finished.sort();
+    let plaintexts = plaintexts.lock().unwrap().clone();
+    (finished[0].1, finished[1].1, plaintexts)
 }
 
 #[test]
 fn challenge_5_35() {
-    // Is this worth it?
-    // The challenge is to negotiate p and g
-    // Examples:
-    // - g = 1; this means that the base is 1, so the resulting values will always be one
-    // - g = p; g % p = 0
-    // - g = p - 1; g % p = 1
-    //
-    // In all cases, these resulted in bad bases, that create easily broken A and B
+    use dh::malicious_g::Substitution;
+    let message = data::Bytes::read_utf8("Negotiated groups are only as good as g");
+    let group = cipher::diffie::MODP_1536.clone();
+    for substitution in [Substitution::One, Substitution::P, Substitution::PMinusOne] {
+        // g = p - 1 gives two possible client keys, so run it a few times to hit both
+        for _ in 0..4 {
+            let client = dh::Client::new(group.clone()).insecure();
+            let server = dh::Server::new().any_group().insecure();
+            let (client_ok, server_ok, read) = malicious_g(substitution, client, server);
+            assert!(client_ok && server_ok);
+            assert_eq!(read, vec![message.clone(), message.clone()]);
+        }
+        // Sticking to known groups, or checking B, stops the attack
+        let client = dh::Client::new(group.clone()).insecure();
+        let (client_ok, server_ok, read) = malicious_g(substitution, client, dh::Server::new());
+        assert!(!client_ok && !server_ok && read.is_empty());
+        let client = dh::Client::new(group.clone());
+        let server = dh::Server::new().any_group().insecure();
+        let (client_ok, _, read) = malicious_g(substitution, client, server);
+        assert!(!client_ok && read.is_empty());
+    }
 }
 
 #[allow(non_snake_case)]
 #[test]
 fn challenge_5_34() {
+    #[derive(Clone)]
     enum Message {
         SendD(BigUint),
         Message(data::Bytes, data::Bytes),
@@ -356,44 +287,35 @@ fn challenge_5_34() {
     };
     comms::comm_channel(a, b);
     // MitM attack (Note that a & b are reused, since they don't change)
-    let m = |atx: Sender<Message>,
-             arx: Receiver<Message>,
-             btx: Sender<Message>,
-             brx: Receiver<Message>| {
-        let _u = if let Message::SendD(u) = arx.recv().unwrap() {
-            u
-        } else {
-            panic!()
-        };
-        btx.send(Message::SendD(cipher::diffie::p_bytes())).unwrap();
-        let _u = if let Message::SendD(u) = brx.recv().unwrap() {
-            u
-        } else {
-            panic!()
-        };
-        atx.send(Message::SendD(cipher::diffie::p_bytes())).unwrap();
-        let (iv, m) = if let Message::Message(iv, m) = arx.recv().unwrap() {
-            (iv, m)
-        } else {
-            panic!()
-        };
-        println!(
-            "M -> {}",
-            cipher::aes_cbc_de(m.clone(), data::Bytes::zero(BLOCK_SIZE), iv.clone())
-        );
-        btx.send(Message::Message(iv, m)).unwrap();
-        let (iv, m) = if let Message::Message(iv, m) = brx.recv().unwrap() {
-            (iv, m)
-        } else {
-            panic!()
-        };
-        println!(
-            "M -> {}",
-            cipher::aes_cbc_de(m.clone(), data::Bytes::zero(BLOCK_SIZE), iv.clone())
-        );
-        atx.send(Message::Message(iv, m)).unwrap();
-    };
-    comms::comm_channel_mitm(a, b, m);
+    impl comms::mitm::PublicKey for Message {
+        fn public_key(&self) -> Option<&BigUint> {
+            match self {
+                Message::SendD(key) => Some(key),
+                _ => None,
+            }
+        }
+
+        fn with_public_key(self, key: BigUint) -> Self {
+            Message::SendD(key)
+        }
+    }
+    let transcript = comms::mitm::parameter_injection(cipher::diffie::p_bytes()).run(a, b);
+    // Both sides computed s = p^x mod p = 0, so the key is known
+    let key = data::Bytes::zero(BLOCK_SIZE);
+    let decrypted: Vec<data::Bytes> = transcript
+        .iter()
+        .filter_map(|entry| match &entry.intercepted {
+            Some(Message::Message(iv, enc)) => {
+                Some(cipher::aes_cbc_de(enc.clone(), key.clone(), iv.clone()).trim_pkcs7())
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(decrypted.len(), 2);
+    for message in decrypted {
+        println!("M -> {}", message);
+        assert_eq!(message, data::Bytes::read_utf8("Simple Msg"));
+    }
 }
 
 #[test]
@@ -416,7 +338,7 @@ fn challenge_5_33() {
 fn challenge_4_32_2() {
     let hmac = mac::HMAC::init(1);
     let file = "test";
-    let actual_sig = data::Bytes::from_vec(hmac.sign(file));
+    let actual_sig = hmac.sign(file.as_bytes());
     let mut times = [0; 20];
     let num_trials = 100;
     for i in 0..20 {
@@ -424,7 +346,7 @@ fn challenge_4_32_2() {
         copied_sig[i] += 0;
         for _ in 0..num_trials {
             let start = std::time::Instant::now();
-            //hmac.verify(file, &copied_sig);
+            //hmac.verify(file.as_bytes(), &copied_sig);
             mac::weak_compare(&actual_sig, &copied_sig);
             times[i] += start.elapsed().as_nanos();
         }
@@ -436,14 +358,13 @@ fn challenge_4_32_2() {
 }
 
 fn challenge_4_32() {
-    use sha::utils::DigestExt;
     let hmac = mac::HMAC::init(1);
     let file = "test";
     let start = std::time::Instant::now();
-    hmac.weak_verify(file, &data::Bytes::from_vec(hmac.sign(file)));
+    hmac.weak_verify(file.as_bytes(), &hmac.sign(file.as_bytes()));
     println!("Normal Verify time: {}ns", start.elapsed().as_nanos());
-    assert!(hmac.verify(file, &data::Bytes::from_vec(hmac.sign(file))));
-    let real_sig = hmac.sign(file);
+    assert!(hmac.verify(file.as_bytes(), &hmac.sign(file.as_bytes())));
+    let real_sig = hmac.sign(file.as_bytes());
     println!(
         "Esitmated time: {}ms",
         real_sig
@@ -452,46 +373,27 @@ fn challenge_4_32() {
             .map(|(i, &b)| i * hmac.get_millis() as usize * b as usize)
             .sum::<usize>()
     );
-    println!("Correct Signature: {:X}", data::Bytes::from_vec(real_sig));
-    let mut signature = data::Bytes::zero(sha::sha1::Sha1::default_len());
-    let mut i = 0;
-    loop {
-        // Timing accuracy could be increased by averaging multiple calls.
-        // However, this likely isn't worth it while using std::thread::sleep,
-        // since it doesn't seem to be more accurate than 1ms anyway. At this
-        // point, these time differences could be significantly impacted by other
-        // processes running concurrently.
-        //
-        // This could be improved, esp. since the base verify (without sleep)
-        // takes less than .02ms, but I would need to write my own sleep method
-        // to have a reasonably good chance to break it.
-        let time = std::time::Instant::now();
-        let correct = hmac.weak_verify(file, &signature);
-        let mut elapsed = time.elapsed().as_millis();
-        //if elapsed as u64 > hmac.get_millis() * 10 {
-        //elapsed += hmac.get_millis() as u128 / 2;
-        //}
-        if correct {
-            break;
-        } else {
-            let key = (elapsed / hmac.get_millis() as u128).min(19);
-            if i != key {
-                println!("Current Signature: {:X}", signature);
-            }
-            i = key;
-            signature[key as usize] = signature[key as usize].wrapping_add(1u8);
-        }
-    }
-    println!("Current Signature: {:X}", signature);
-    assert!(hmac.verify(file, &signature));
+    println!("Correct Signature: {:X}", real_sig);
+    // A single measurement per candidate isn't reliable with a 1ms delay, since sleep itself
+    // isn't much more accurate than that. Taking several samples of each candidate, and only
+    // accepting a byte once it clearly stands out (backing up when nothing does) works around it.
+    let signature = timing::TimingAttack::new()
+        .samples(5)
+        .statistic(timing::Statistic::TrimmedMean(0.2))
+        .recover(
+            |signature| hmac.weak_verify(file.as_bytes(), signature),
+            real_sig.len(),
+        )
+        .expect("Failed to recover signature");
+    println!("Recovered Signature: {:X}", signature);
+    assert!(hmac.verify(file.as_bytes(), &signature));
 }
 
 fn challenge_4_31() {
-    use sha::utils::DigestExt;
     let hmac = mac::HMAC::init(100);
     let file = "test";
-    assert!(hmac.verify(file, &data::Bytes::from_vec(hmac.sign(file))));
-    let real_sig = hmac.sign(file);
+    assert!(hmac.verify(file.as_bytes(), &hmac.sign(file.as_bytes())));
+    let real_sig = hmac.sign(file.as_bytes());
     println!(
         "Esitmated time: {}ms",
         real_sig
@@ -500,173 +402,49 @@ fn challenge_4_31() {
             .map(|(i, &b)| i * 100 * b as usize)
             .sum::<usize>()
     );
-    println!("Correct Signature: {:X}", data::Bytes::from_vec(real_sig));
-    let mut signature = data::Bytes::zero(sha::sha1::Sha1::default_len());
-    let mut i = 0;
-    loop {
-        let time = std::time::Instant::now();
-        let correct = hmac.weak_verify(file, &signature);
-        let elapsed = time.elapsed().as_millis() + 50;
-        if correct {
-            break;
-        } else {
-            let key = elapsed / 100;
-            if i != key {
-                println!("Current Signature: {:X}", signature);
-            }
-            i = key;
-            signature[key as usize] = signature[key as usize].wrapping_add(1u8);
-        }
-    }
-    assert!(hmac.verify(file, &signature));
+    println!("Correct Signature: {:X}", real_sig);
+    // Attack over an actual socket, rather than calling weak_verify directly
+    let server = comms::http::HmacServer::start(hmac).unwrap();
+    let attacker = comms::http::TimingAttacker::new(server.addr()).samples(1);
+    let signature = attacker
+        .recover(file, real_sig.len())
+        .expect("Failed to recover signature");
+    println!("Recovered Signature: {:X}", signature);
+    assert_eq!(signature, real_sig);
 }
 
+#[test]
 fn challenge_4_30() {
-    use sha::utils::DigestExt;
     let key = mac::SecrectDigest::md4();
     let message = file::File::read_hex_file("data_1_4").next().unwrap();
     let mac = key.sign(&message);
-    println!("mac len: {}", mac.len());
     assert!(key.verify(&message, &mac));
-    // cheap method to just get the key size
-    // This could just be trial and error, since there are only 64 possible values (since the
-    // message is padded to 64 bytes)
-    let key_len = key.len();
-
-    let padded_message = pad_md4(data::Bytes::zero(key_len) + message.clone());
-    //println!("{:X?}", padded_message.split(8));
-    let mut padded_message = padded_message.truncate_start(key_len);
-    println!("hasher size: {}", std::mem::size_of::<md4::Md4>());
-    let state: Vec<u32> = mac
-        .split(4)
-        .into_iter()
-        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
-        .collect();
-    println!("Collected Mac: {:X?}", mac);
-    println!("Collected State: {:X?}", state);
-    let mut broken_hasher: md4::Md4 = unsafe {
-        let mut md4 = md4::Md4::default();
-        //use digest::Digest;
-        md4.update(data::Bytes::zero(0).to_bytes());
-        md4.update(padded_message.to_bytes());
-        let mut raw: [u32; 24] = std::mem::transmute(md4);
-        println!("{:X?}{:X?}", &raw[0..2], &raw[20..]);
-        //println!("Raw memory");
-        const S: [u32; 4] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476];
-        for r in raw.iter_mut() {
-            //println!(" {:X}", r);
-        }
-        //let message_len: [u32; 2] = std::mem::transmute((key_len + message.len()) as u64);
-        //raw[0] = message_len[0];
-        //raw[1] = message_len[1];
-        println!(
-            "message_len: {:X?}, {:X}",
-            &raw[0..2],
-            key_len + message.len()
-        );
-        raw[20] = state[0];
-        raw[21] = state[1];
-        raw[22] = state[2];
-        raw[23] = state[3];
-        std::mem::transmute(raw)
-    };
-    use digest::Digest;
-    let addition = data::Bytes::read_utf8(";admin=true;");
-    //broken_hasher.update(addition.to_bytes());
-    //padded_message += addition;
-
-    unsafe {
-        let mut md4: md4::Md4 = std::mem::transmute_copy(&broken_hasher);
-        let mut gen_arr: () = Default::default();
-        //use digest::FixedOutput;
-        //md4.finalize_into_dirty(&mut gen_arr);
-        let raw: [u32; 24] = std::mem::transmute(md4);
-        println!("Copy:");
-        println!("{:X} => {:X?}", padded_message, &raw[20..]);
-        println!("len: {:X?}", &raw[0..2]);
-    }
-
-    let new_mac = data::Bytes::from_bytes(&broken_hasher.finalize()[..]);
-    println!("mac: {:X}", new_mac);
-    println!("mes: {:X}", key.sign(&padded_message));
-    //assert_eq!(key.sign(&padded_message), &new_mac);
-
-    //// Trim fake key
-    //let padded_message = padded_message.truncate_start(key_len);
-    ////println!("{}", padded_message);
-    ////assert_eq!(new_mac, mac);
-    //assert_eq!(key.sign(&padded_message), &new_mac);
-}
-
-fn pad_md4(mut message: data::Bytes) -> data::Bytes {
-    let mut ret = data::Bytes::empty();
-    let mut buffer: block_buffer::BlockBuffer<digest::consts::U64, Eager> =
-        block_buffer::BlockBuffer::default();
-    buffer.set_data(message.as_mut_bytes(), |b| for block in b {
 
-    });
-    buffer.len64_padding_le(message.len() as u64, |b| ret += &b[..]);
-    ret
+    // The key size is found by trial and error. It's unlikely to be longer than a block, and
+    // the attack only needs to know the length of key + message anyway
+    let addition = data::Bytes::read_utf8(";admin=true;");
+    let (forged_message, new_mac) =
+        hash::length_extension::forge::<hash::md4::Md4>(&mac, &message, &addition, 0..64, |m, t| {
+            key.verify(m, t)
+        })
+        .expect("Failed to find key length");
+    assert_eq!(key.sign(&forged_message), &new_mac);
 }
 
 #[test]
 fn challenge_4_29() {
-    use sha::utils::DigestExt;
-    let key = mac::SecrectDigest::md4();
+    let key = mac::SecrectDigest::sha1();
     let message = file::File::read_hex_file("data_1_4").next().unwrap();
     let mac = key.sign(&message);
-    println!("mac len: {}", mac.len());
     assert!(key.verify(&message, &mac));
-    // cheap method to just get the key size
-    // This could just be trial and error, since there are only 64 possible values (since the
-    // message is padded to 64 bytes)
-    let key_len = key.len();
-
-    let state: Vec<u32> = mac
-        .split(4)
-        .into_iter()
-        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
-        .collect();
-    // the zero bytes added to the message represent the size of the key
-    let (mut padded_message, mut sha1_gen) =
-        sha1_pad_bytes(data::Bytes::zero(key_len) + message.clone());
-    sha1_gen.0 = [state[0], state[1], state[2], state[3], state[4]];
-    println!("Padded len: {}", padded_message.len());
-    println!("{:X}", padded_message);
 
     let addition = data::Bytes::read_utf8("Some new data");
-    let blocks_in_message = padded_message.len() / 64;
-    padded_message += addition;
-    use sha::utils::*;
-    // .skip(n), n is the number of blocks in the original message
-    for block in padded_message
-        .to_vec()
-        .pad_blocks(64, sha::sha1::ops::pad)
-        .skip(blocks_in_message)
-    // number of blocks in the original message
-    {
-        sha::sha1::ops::digest_block(&mut sha1_gen.0, &block[..]);
-    }
-    let new_mac = data::Bytes::from_vec(sha1_gen.to_bytes()); // Without the for loop to add new sha1 blocks
-
-    // Trim fake key
-    let padded_message = padded_message.truncate_start(key_len);
-    //println!("{}", padded_message);
-    //assert_eq!(new_mac, mac);
-    assert_eq!(key.sign(&padded_message), &new_mac);
-}
-
-fn sha1_pad_bytes(message: data::Bytes) -> (data::Bytes, sha::sha1::Sha1) {
-    use sha::utils::*;
-    let mut padded_message = data::Bytes::zero(0);
-    let mut sha1 = sha::sha1::Sha1::default();
-    let padding_vec = message.to_vec();
-    let padded_blocks = padding_vec.pad_blocks(64, sha::sha1::ops::pad);
-    for block in padded_blocks {
-        sha::sha1::ops::digest_block(&mut sha1.0, &block[..]);
-        padded_message += data::Bytes::from_vec(block);
-    }
-    (padded_message, sha1)
+    let (forged_message, new_mac) =
+        hash::length_extension::forge::<hash::sha1::Sha1>(&mac, &message, &addition, 0..64, |m, t| {
+            key.verify(m, t)
+        })
+        .expect("Failed to find key length");
+    assert_eq!(key.sign(&forged_message), &new_mac);
 }
 
 #[test]
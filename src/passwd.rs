@@ -1,7 +1,11 @@
-use std::{collections::HashMap, hash::Hasher};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
 
 use crate::data::Bytes;
-use sha::sha256;
+use crate::hash::{bcrypt, pbkdf2::pbkdf2, scrypt::scrypt, sha2::Sha256, Digest};
 
 //
 // passwd.rs
@@ -9,51 +13,226 @@ use sha::sha256;
 // Distributed under terms of the MIT license.
 //
 
+/// First line of a saved store, bumped whenever the file format changes
+const STORE_HEADER: &str = "# passwd store v1";
+
+/// Alphabet for the unpadded base64 used in PHC strings
+const PHC_B64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Largest scrypt working memory, 128 * r * N bytes, accepted from a PHC string, so one line of a
+/// store can't make `verify` allocate without limit
+const MAX_SCRYPT_MEMORY: u64 = 1 << 28;
+
+/// Largest scrypt parallelism accepted from a PHC string. The p mixes run one after another, so
+/// this bounds the time rather than the memory
+const MAX_SCRYPT_P: u32 = 16;
+
+/// Largest iteration count accepted from a PHC string for `sha256` and `pbkdf2-sha256`, well
+/// above the few hundred thousand PBKDF2 is usually run with
+const MAX_ITERATIONS: u32 = 1 << 24;
+
+/// How passwords are hashed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    /// Salted SHA-256, rehashed `iterations - 1` more times. A single iteration is what the SRP
+    /// challenges rely on
+    Sha256 { iterations: u32 },
+    /// PBKDF2-HMAC-SHA256, with a 32 byte output
+    Pbkdf2 { iterations: u32 },
+    /// scrypt with N = 2^`log_n`, and a 32 byte output
+    Scrypt { log_n: u8, r: u32, p: u32 },
+    /// bcrypt, stored in its own `$2b$` format rather than as a PHC string
+    Bcrypt { cost: u32 },
+}
+
+impl Default for Algorithm {
+    fn default() -> Self {
+        Algorithm::Sha256 { iterations: 1 }
+    }
+}
+
+impl Algorithm {
+    fn hash(&self, salt: &Bytes, secret: &[u8]) -> Bytes {
+        match self {
+            Algorithm::Sha256 { iterations } => {
+                let mut hasher = Sha256::default();
+                hasher.update(salt);
+                hasher.update(secret);
+                let mut hash = hasher.finalize();
+                for _ in 1..*iterations {
+                    hash = Sha256::digest(&hash);
+                }
+                hash
+            }
+            Algorithm::Pbkdf2 { iterations } => {
+                pbkdf2::<Sha256>(secret, salt, *iterations, Sha256::OUTPUT_SIZE)
+            }
+            Algorithm::Scrypt { log_n, r, p } => scrypt(secret, salt, *log_n, *r, *p, 32),
+            Algorithm::Bcrypt { cost } => bcrypt::bcrypt(*cost, salt, secret),
+        }
+    }
+
+    /// Identifier and parameters, as they appear in a PHC string
+    fn phc_id(&self) -> (&'static str, String) {
+        match self {
+            Algorithm::Sha256 { iterations } => ("sha256", format!("i={}", iterations)),
+            Algorithm::Pbkdf2 { iterations } => ("pbkdf2-sha256", format!("i={}", iterations)),
+            Algorithm::Scrypt { log_n, r, p } => {
+                ("scrypt", format!("ln={},r={},p={}", log_n, r, p))
+            }
+            Algorithm::Bcrypt { cost } => ("2b", format!("{:02}", cost)),
+        }
+    }
+
+    fn from_phc_id(id: &str, params: &str) -> Option<Self> {
+        let params: HashMap<&str, &str> = params
+            .split(',')
+            .map(|param| param.split_once('='))
+            .collect::<Option<_>>()?;
+        let param = |name| params.get(name)?.parse().ok().filter(|&i| i > 0);
+        let iterations = || param("i").filter(|&i| i <= MAX_ITERATIONS);
+        match id {
+            "sha256" => Some(Algorithm::Sha256 {
+                iterations: iterations()?,
+            }),
+            "pbkdf2-sha256" => Some(Algorithm::Pbkdf2 {
+                iterations: iterations()?,
+            }),
+            "scrypt" => {
+                let log_n: u8 = param("ln")?.try_into().ok().filter(|&ln| ln < 64)?;
+                let r: u32 = param("r")?;
+                let p: u32 = param("p")?;
+                // Can't overflow, since log_n < 64 and r < 2^32
+                let memory = (128 * r as u128) << log_n;
+                if memory > MAX_SCRYPT_MEMORY as u128 || p > MAX_SCRYPT_P {
+                    return None;
+                }
+                Some(Algorithm::Scrypt { log_n, r, p })
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Passwd {
     pub salt: Bytes,
     pub hash: Bytes,
+    pub algorithm: Algorithm,
 }
 
 impl Passwd {
-    fn new(pepper: &Bytes, passwd: impl AsRef<str>) -> Self {
+    fn new(pepper: &Bytes, passwd: impl AsRef<str>, algorithm: Algorithm) -> Self {
         let salt = Bytes::rand(16);
-        let mut hasher = sha256::Sha256::default();
-        //hasher.write(pepper);
-        hasher.write(&salt);
-        hasher.write(passwd.as_ref().as_bytes());
         Self {
+            hash: algorithm.hash(&salt, &peppered(pepper, passwd.as_ref())),
             salt,
-            hash: Bytes::from_bytes(&hasher.finish().to_le_bytes()),
+            algorithm,
         }
     }
 
     fn verify(&self, pepper: &Bytes, passwd: impl AsRef<str>) -> bool {
-        let mut hasher = sha256::Sha256::default();
-        //hasher.write(pepper);
-        hasher.write(&self.salt);
-        hasher.write(passwd.as_ref().as_bytes());
-        &hasher.finish().to_le_bytes() == self.hash.to_bytes()
+        self.algorithm
+            .hash(&self.salt, &peppered(pepper, passwd.as_ref()))
+            .ct_eq(&self.hash)
+    }
+
+    /// Serializes as `$<algorithm>$<params>$<salt>$<hash>`, with unpadded base64
+    ///
+    /// bcrypt hashes use the `$2b$<cost>$<salt><hash>` format everything else expects for them
+    pub fn to_phc(&self) -> String {
+        if let Algorithm::Bcrypt { cost } = self.algorithm {
+            return bcrypt::encode(cost, &self.salt, &self.hash);
+        }
+        let (id, params) = self.algorithm.phc_id();
+        format!(
+            "${}${}${}${}",
+            id,
+            params,
+            self.salt.to_64_with(PHC_B64),
+            self.hash.to_64_with(PHC_B64)
+        )
+    }
+
+    pub fn from_phc(s: &str) -> Option<Self> {
+        if let Some((cost, salt, hash)) = bcrypt::decode(s) {
+            return Some(Self {
+                salt,
+                hash,
+                algorithm: Algorithm::Bcrypt { cost },
+            });
+        }
+        let mut parts = s.strip_prefix('$')?.split('$');
+        let algorithm = Algorithm::from_phc_id(parts.next()?, parts.next()?)?;
+        let salt = Bytes::read_64_with(parts.next()?, PHC_B64)?;
+        let hash = Bytes::read_64_with(parts.next()?, PHC_B64)?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            salt,
+            hash,
+            algorithm,
+        })
     }
 }
 
+/// The pepper is prepended to the password, so an empty pepper leaves it unchanged
+fn peppered(pepper: &Bytes, passwd: &str) -> Bytes {
+    pepper.clone() + passwd.as_bytes()
+}
+
+/// A username that `PasswdStore::add_user` refused
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidUsername(pub String);
+
+fn valid_username(username: &str) -> bool {
+    !username.contains(|c| c == ':' || c == '\n' || c == '\r')
+}
+
 #[derive(Debug)]
 pub struct PasswdStore {
     pub pepper: Bytes,
     pub db: HashMap<String, Passwd>,
+    algorithm: Algorithm,
 }
 
 impl PasswdStore {
+    /// An empty store, with a random pepper
     pub fn new() -> Self {
         Self {
             pepper: Bytes::rand(16),
             db: HashMap::default(),
+            algorithm: Algorithm::default(),
         }
     }
 
-    pub fn add_user(&mut self, username: impl Into<String>, passwd: impl AsRef<str>) {
-        self.db
-            .insert(username.into(), Passwd::new(&self.pepper, passwd));
+    /// Replaces the pepper. It isn't stored with the hashes, so it has to be kept to reload them
+    pub fn pepper(mut self, pepper: Bytes) -> Self {
+        self.pepper = pepper;
+        self
+    }
+
+    /// Sets the algorithm used for users added from now on. Existing users keep their own
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Adds or replaces a user. Usernames can't contain `:` or line breaks, which would corrupt
+    /// the saved store
+    pub fn add_user(
+        &mut self,
+        username: impl Into<String>,
+        passwd: impl AsRef<str>,
+    ) -> Result<(), InvalidUsername> {
+        let username = username.into();
+        if !valid_username(&username) {
+            return Err(InvalidUsername(username));
+        }
+        let passwd = Passwd::new(&self.pepper, passwd, self.algorithm);
+        self.db.insert(username, passwd);
+        Ok(())
     }
 
     pub fn verify(&mut self, username: impl AsRef<str>, pass: impl AsRef<str>) -> bool {
@@ -63,4 +242,174 @@ impl PasswdStore {
             false
         }
     }
+
+    /// Writes one `username:<PHC string>` line per user, sorted by username
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut users: Vec<_> = self.db.iter().collect();
+        users.sort_by(|a, b| a.0.cmp(b.0));
+        let mut file = String::from(STORE_HEADER) + "\n";
+        for (username, passwd) in users {
+            file += &format!("{}:{}\n", username, passwd.to_phc());
+        }
+        fs::write(path, file)
+    }
+
+    /// Loads a store written by `save`, with the pepper it was created with
+    pub fn load(path: impl AsRef<Path>, pepper: Bytes) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(ErrorKind::InvalidData, msg);
+        let file = fs::read_to_string(path)?;
+        let mut lines = file.lines();
+        if lines.next() != Some(STORE_HEADER) {
+            return Err(invalid("Unsupported passwd store version".to_string()));
+        }
+        let mut store = Self::new().pepper(pepper);
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (username, phc) = line
+                .rsplit_once(':')
+                .ok_or_else(|| invalid(format!("Missing username: {}", line)))?;
+            if !valid_username(username) {
+                return Err(invalid(format!("Invalid username: {}", username)));
+            }
+            let passwd =
+                Passwd::from_phc(phc).ok_or_else(|| invalid(format!("Invalid hash: {}", phc)))?;
+            store.db.insert(username.to_string(), passwd);
+        }
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algorithms() {
+        let mut store = PasswdStore::new();
+        store.add_user("sha", "hunter2").unwrap();
+        let mut store = store.algorithm(Algorithm::Pbkdf2 { iterations: 1000 });
+        store.add_user("pbkdf2", "hunter2").unwrap();
+        let mut store = store.algorithm(Algorithm::Sha256 { iterations: 1000 });
+        store.add_user("sha-iterated", "hunter2").unwrap();
+        let mut store = store.algorithm(Algorithm::Scrypt {
+            log_n: 4,
+            r: 8,
+            p: 1,
+        });
+        store.add_user("scrypt", "hunter2").unwrap();
+        let mut store = store.algorithm(Algorithm::Bcrypt { cost: 4 });
+        store.add_user("bcrypt", "hunter2").unwrap();
+        assert_eq!(
+            store.db["sha"].algorithm,
+            Algorithm::Sha256 { iterations: 1 }
+        );
+        assert_eq!(
+            store.db["pbkdf2"].algorithm,
+            Algorithm::Pbkdf2 { iterations: 1000 }
+        );
+        for user in ["sha", "pbkdf2", "sha-iterated", "scrypt", "bcrypt"] {
+            assert!(store.verify(user, "hunter2"));
+            assert!(!store.verify(user, "hunter3"));
+        }
+        assert!(!store.verify("nobody", "hunter2"));
+    }
+
+    #[test]
+    fn pepper() {
+        let mut store = PasswdStore::new().pepper(Bytes::new());
+        store.add_user("admin", "admin").unwrap();
+        let passwd = &store.db["admin"];
+        let mut hasher = Sha256::default();
+        hasher.update(&passwd.salt);
+        hasher.update(b"admin");
+        assert_eq!(passwd.hash, hasher.finalize());
+
+        let mut store = PasswdStore::new();
+        store.add_user("admin", "admin").unwrap();
+        let passwd = &store.db["admin"];
+        assert!(passwd.verify(&store.pepper, "admin"));
+        assert!(!passwd.verify(&Bytes::rand(16), "admin"));
+    }
+
+    #[test]
+    fn phc() {
+        let passwd = Passwd::new(
+            &Bytes::new(),
+            "hunter2",
+            Algorithm::Pbkdf2 { iterations: 10 },
+        );
+        let phc = passwd.to_phc();
+        assert!(phc.starts_with("$pbkdf2-sha256$i=10$"));
+        let parsed = Passwd::from_phc(&phc).unwrap();
+        assert_eq!(parsed.algorithm, passwd.algorithm);
+        assert_eq!(parsed.salt, passwd.salt);
+        assert_eq!(parsed.hash, passwd.hash);
+        assert!(parsed.verify(&Bytes::new(), "hunter2"));
+
+        for algorithm in [
+            Algorithm::Scrypt {
+                log_n: 4,
+                r: 2,
+                p: 3,
+            },
+            Algorithm::Bcrypt { cost: 4 },
+        ] {
+            let passwd = Passwd::new(&Bytes::new(), "hunter2", algorithm);
+            let parsed = Passwd::from_phc(&passwd.to_phc()).unwrap();
+            assert_eq!(parsed.algorithm, algorithm);
+            assert!(parsed.verify(&Bytes::new(), "hunter2"));
+        }
+        assert!(
+            Passwd::new(&Bytes::new(), "", Algorithm::Bcrypt { cost: 4 })
+                .to_phc()
+                .starts_with("$2b$04$")
+        );
+
+        assert!(Passwd::from_phc("$md5$i=1$AAAA$AAAA").is_none());
+        assert!(Passwd::from_phc("$scrypt$ln=4,r=8$AAAA$AAAA").is_none());
+        assert!(Passwd::from_phc("$sha256$i=0$AAAA$AAAA").is_none());
+        assert!(Passwd::from_phc("$sha256$i=1$AAAA").is_none());
+        assert!(Passwd::from_phc("$sha256$i=1$AAAA$AAAA$AAAA").is_none());
+        // scrypt parameters that would take gigabytes, or forever, to check
+        assert!(Passwd::from_phc("$scrypt$ln=40,r=8,p=1$AAAA$AAAA").is_none());
+        assert!(Passwd::from_phc("$scrypt$ln=14,r=4096,p=1$AAAA$AAAA").is_none());
+        assert!(Passwd::from_phc("$scrypt$ln=60,r=4294967295,p=1$AAAA$AAAA").is_none());
+        assert!(Passwd::from_phc("$scrypt$ln=4,r=8,p=1000000$AAAA$AAAA").is_none());
+        assert!(Passwd::from_phc("$scrypt$ln=17,r=8,p=1$AAAA$AAAA").is_some());
+        // Likewise iteration counts
+        for id in ["sha256", "pbkdf2-sha256"] {
+            let phc = |i: u32| format!("${}$i={}$AAAA$AAAA", id, i);
+            assert!(Passwd::from_phc(&phc(4294967295)).is_none());
+            assert!(Passwd::from_phc(&phc(MAX_ITERATIONS + 1)).is_none());
+            assert!(Passwd::from_phc(&phc(MAX_ITERATIONS)).is_some());
+        }
+    }
+
+    #[test]
+    fn save_load() {
+        let path = std::env::temp_dir().join(format!("passwd-{}", Bytes::rand(8).to_hex()));
+        let mut store = PasswdStore::new().algorithm(Algorithm::Pbkdf2 { iterations: 100 });
+        store.add_user("alice", "correct horse").unwrap();
+        store.add_user("bob", "battery staple").unwrap();
+        store.save(&path).unwrap();
+
+        let mut loaded = PasswdStore::load(&path, store.pepper.clone()).unwrap();
+        assert!(loaded.verify("alice", "correct horse"));
+        assert!(loaded.verify("bob", "battery staple"));
+        assert!(!loaded.verify("alice", "battery staple"));
+
+        let mut wrong_pepper = PasswdStore::load(&path, Bytes::rand(16)).unwrap();
+        assert!(!wrong_pepper.verify("alice", "correct horse"));
+
+        assert_eq!(
+            store.add_user("mallory\nadmin", "x"),
+            Err(InvalidUsername("mallory\nadmin".to_string()))
+        );
+        assert!(store.add_user("a:b", "x").is_err());
+        fs::write(&path, "# passwd store v1\na:b:$sha256$i=1$AAAA$AAAA\n").unwrap();
+        assert!(PasswdStore::load(&path, Bytes::new()).is_err());
+
+        fs::write(&path, "# passwd store v0\n").unwrap();
+        assert!(PasswdStore::load(&path, Bytes::new()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
 }
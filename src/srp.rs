@@ -0,0 +1,724 @@
+//
+// srp.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! SRP-6a (RFC 5054), as client and server state machines
+//!
+//! ```text
+//! Client                                  Server
+//! Hello { username, A = g^a }         ->
+//!                                     <-  Challenge { salt, B = k*v + g^b }
+//! ClientProof { M1 }                  ->
+//!                                     <-  ServerProof { M2 }
+//! ```
+//!
+//! Where x = H(salt | H(username ":" password)), v = g^x, u = H(PAD(A) | PAD(B)), k = H(N | PAD(g))
+//! and both sides arrive at S = (B - k*g^x)^(a + u*x) = (A * v^u)^b, with K = H(PAD(S)).
+
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+
+use lazy_static::lazy_static;
+use num_bigint::{BigUint, RandBigInt};
+
+use crate::comms::{self, Transport, Wire};
+use crate::data::Bytes;
+use crate::dictionary::{Cracked, Dictionary};
+use crate::hash::{hmac::Hmac, Digest};
+
+lazy_static! {
+    pub static ref RFC5054_1024: Group = Group::new(
+        "EEAF0AB9ADB38DD69C33F80AFA8FC5E86072618775FF3C0B9EA2314C9C256576D674DF7496EA81D3383B4813D69\
+         2C6E0E0D5D8E250B98BE48E495C1D6089DAD15DC7D7B46154D6B6CE8EF4AD69B15D4982559B297BCF1885C529F5\
+         66660E57EC68EDBC3C05726CC02FD4CBF4976EAA9AFD5138FE8376435B9FC61D2FC0EB06E3",
+        2
+    );
+    pub static ref RFC5054_1536: Group = Group::new(
+        "9DEF3CAFB939277AB1F12A8617A47BBBDBA51DF499AC4C80BEEEA9614B19CC4D5F4F5F556E27CBDE51C6A94BE46\
+         07A291558903BA0D0F84380B655BB9A22E8DCDF028A7CEC67F0D08134B1C8B97989149B609E0BE3BAB63D475483\
+         81DBC5B1FC764E3F4B53DD9DA1158BFD3E2B9C8CF56EDF019539349627DB2FD53D24B7C48665772E437D6C7F8CE\
+         442734AF7CCB7AE837C264AE3A9BEB87F8A2FE9B8B5292E5A021FFF5E91479E8CE7A28C2442C6F315180F93499A\
+         234DCF76E3FED135F9BB",
+        2
+    );
+    pub static ref RFC5054_2048: Group = Group::new(
+        "AC6BDB41324A9A9BF166DE5E1389582FAF72B6651987EE07FC3192943DB56050A37329CBB4A099ED8193E075776\
+         7A13DD52312AB4B03310DCD7F48A9DA04FD50E8083969EDB767B0CF6095179A163AB3661A05FBD5FAAAE82918A9\
+         962F0B93B855F97993EC975EEAA80D740ADBF4FF747359D041D5C33EA71D281E446B14773BCA97B43A23FB80167\
+         6BD207A436C6481F1D2B9078717461A5B9D32E688F87748544523B524B0D57D5EA77A2775D2ECFA032CFBDBF52F\
+         B3786160279004E57AE6AF874E7303CE53299CCC041C7BC308D82A5698F3A8D0C38271AE35F8E9DBFBB694B5C80\
+         3D89F7AE435DE236D525F54759B65E372FCD68EF20FA7111F9E4AFF73",
+        2
+    );
+}
+
+/// A safe prime modulus and generator
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub n: BigUint,
+    pub g: BigUint,
+}
+
+impl Group {
+    fn new(n: &str, g: u32) -> Self {
+        Self {
+            n: BigUint::parse_bytes(n.as_bytes(), 16).unwrap(),
+            g: BigUint::from(g),
+        }
+    }
+
+    /// Left pads `value` with zeros to the length of N
+    pub fn pad(&self, value: &BigUint) -> Bytes {
+        let bytes = value.to_bytes_be();
+        let len = ((self.n.bits() + 7) / 8) as usize;
+        Bytes::zero(len.saturating_sub(bytes.len())) + Bytes::from_vec(bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Hello {
+        username: String,
+        a_pub: BigUint,
+    },
+    Challenge {
+        salt: Bytes,
+        b_pub: BigUint,
+    },
+    ClientProof {
+        m1: Bytes,
+    },
+    ServerProof {
+        m2: Bytes,
+    },
+    /// Sent by the server in place of a challenge or proof when authentication fails
+    Failed,
+}
+
+impl Wire for Message {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::Hello { username, a_pub } => {
+                0u8.encode(out);
+                username.encode(out);
+                a_pub.encode(out);
+            }
+            Message::Challenge { salt, b_pub } => {
+                1u8.encode(out);
+                salt.encode(out);
+                b_pub.encode(out);
+            }
+            Message::ClientProof { m1 } => {
+                2u8.encode(out);
+                m1.encode(out);
+            }
+            Message::ServerProof { m2 } => {
+                3u8.encode(out);
+                m2.encode(out);
+            }
+            Message::Failed => 4u8.encode(out),
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, comms::Error> {
+        Ok(match u8::decode(input)? {
+            0 => Message::Hello {
+                username: String::decode(input)?,
+                a_pub: BigUint::decode(input)?,
+            },
+            1 => Message::Challenge {
+                salt: Bytes::decode(input)?,
+                b_pub: BigUint::decode(input)?,
+            },
+            2 => Message::ClientProof {
+                m1: Bytes::decode(input)?,
+            },
+            3 => Message::ServerProof {
+                m2: Bytes::decode(input)?,
+            },
+            4 => Message::Failed,
+            tag => return comms::wire::unknown_tag(tag),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A message arrived out of order
+    UnexpectedMessage,
+    UnknownUser,
+    /// A or B was 0 mod N, which would force the shared secret to 0
+    InvalidPublicKey,
+    /// The other side's proof didn't match
+    BadProof,
+    /// The server sent `Failed`
+    Rejected,
+    /// The connection failed, or the other side sent something undecodable
+    Transport(comms::Error),
+}
+
+impl From<comms::Error> for Error {
+    fn from(e: comms::Error) -> Self {
+        Error::Transport(e)
+    }
+}
+
+/// The SRP computations, over a group and hash
+#[derive(Debug)]
+pub struct Srp<H: Digest> {
+    pub group: Group,
+    hash: PhantomData<H>,
+}
+
+impl<H: Digest> Clone for Srp<H> {
+    fn clone(&self) -> Self {
+        Self::new(self.group.clone())
+    }
+}
+
+impl<H: Digest> Srp<H> {
+    pub fn new(group: Group) -> Self {
+        Self {
+            group,
+            hash: PhantomData,
+        }
+    }
+
+    fn hash(&self, parts: &[&[u8]]) -> Bytes {
+        let mut hasher = H::default();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize()
+    }
+
+    fn hash_int(&self, parts: &[&[u8]]) -> BigUint {
+        BigUint::from_bytes_be(&self.hash(parts))
+    }
+
+    /// Multiplier parameter, k = H(N | PAD(g))
+    pub fn k(&self) -> BigUint {
+        self.hash_int(&[&self.group.n.to_bytes_be(), &self.group.pad(&self.group.g)])
+    }
+
+    /// Private key, x = H(salt | H(username ":" password))
+    pub fn x(&self, salt: &Bytes, username: &str, password: &str) -> BigUint {
+        let inner = self.hash(&[username.as_bytes(), b":", password.as_bytes()]);
+        self.hash_int(&[salt, &inner])
+    }
+
+    /// Password verifier, v = g^x
+    pub fn verifier(&self, salt: &Bytes, username: &str, password: &str) -> BigUint {
+        self.group
+            .g
+            .modpow(&self.x(salt, username, password), &self.group.n)
+    }
+
+    /// Scrambling parameter, u = H(PAD(A) | PAD(B))
+    pub fn u(&self, a_pub: &BigUint, b_pub: &BigUint) -> BigUint {
+        self.hash_int(&[&self.group.pad(a_pub), &self.group.pad(b_pub)])
+    }
+
+    /// Session key, K = H(PAD(S))
+    pub fn session_key(&self, s: &BigUint) -> Bytes {
+        self.hash(&[&self.group.pad(s)])
+    }
+
+    /// Client proof, M1 = H(H(N) ^ H(g) | H(username) | salt | A | B | K)
+    pub fn m1(
+        &self,
+        username: &str,
+        salt: &Bytes,
+        a_pub: &BigUint,
+        b_pub: &BigUint,
+        key: &Bytes,
+    ) -> Bytes {
+        let group =
+            self.hash(&[&self.group.n.to_bytes_be()]) ^ self.hash(&[&self.group.g.to_bytes_be()]);
+        self.hash(&[
+            &group,
+            &self.hash(&[username.as_bytes()]),
+            salt,
+            &a_pub.to_bytes_be(),
+            &b_pub.to_bytes_be(),
+            key,
+        ])
+    }
+
+    /// Server proof, M2 = H(A | M1 | K)
+    pub fn m2(&self, a_pub: &BigUint, m1: &Bytes, key: &Bytes) -> Bytes {
+        self.hash(&[&a_pub.to_bytes_be(), m1, key])
+    }
+
+    fn private_key(&self) -> BigUint {
+        rand::thread_rng().gen_biguint(256)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ClientState {
+    Start,
+    SentHello { a: BigUint, a_pub: BigUint },
+    SentProof { m2: Bytes, key: Bytes },
+    Done { key: Bytes },
+}
+
+#[derive(Debug, Clone)]
+pub struct Client<H: Digest> {
+    srp: Srp<H>,
+    username: String,
+    password: String,
+    state: ClientState,
+}
+
+impl<H: Digest> Client<H> {
+    pub fn new(srp: Srp<H>, username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            srp,
+            username: username.into(),
+            password: password.into(),
+            state: ClientState::Start,
+        }
+    }
+
+    /// The first message of the exchange
+    pub fn start(&mut self) -> Message {
+        let a = self.srp.private_key();
+        let a_pub = self.srp.group.g.modpow(&a, &self.srp.group.n);
+        self.state = ClientState::SentHello {
+            a,
+            a_pub: a_pub.clone(),
+        };
+        Message::Hello {
+            username: self.username.clone(),
+            a_pub,
+        }
+    }
+
+    /// Handles a message from the server, returning the reply if there is one
+    pub fn handle(&mut self, message: Message) -> Result<Option<Message>, Error> {
+        let n = &self.srp.group.n;
+        match (&self.state, message) {
+            (_, Message::Failed) => Err(Error::Rejected),
+            (ClientState::SentHello { a, a_pub }, Message::Challenge { salt, b_pub }) => {
+                if (&b_pub % n).bits() == 0 {
+                    return Err(Error::InvalidPublicKey);
+                }
+                let u = self.srp.u(a_pub, &b_pub);
+                let x = self.srp.x(&salt, &self.username, &self.password);
+                let kv = self.srp.k() * self.srp.group.g.modpow(&x, n) % n;
+                let base = (&b_pub + n - kv) % n;
+                let s = base.modpow(&(a + u * x), n);
+                let key = self.srp.session_key(&s);
+                let m1 = self.srp.m1(&self.username, &salt, a_pub, &b_pub, &key);
+                let m2 = self.srp.m2(a_pub, &m1, &key);
+                self.state = ClientState::SentProof { m2, key };
+                Ok(Some(Message::ClientProof { m1 }))
+            }
+            (ClientState::SentProof { m2, key }, Message::ServerProof { m2: proof }) => {
+                if !m2.ct_eq(&proof) {
+                    return Err(Error::BadProof);
+                }
+                self.state = ClientState::Done { key: key.clone() };
+                Ok(None)
+            }
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    /// The shared key, once the server has proven it knows it too
+    pub fn session_key(&self) -> Option<&Bytes> {
+        match &self.state {
+            ClientState::Done { key } => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Runs the whole exchange over a transport, returning the session key
+    pub fn run(mut self, transport: &mut impl Transport<Message>) -> Result<Bytes, Error> {
+        transport.send(self.start())?;
+        while self.session_key().is_none() {
+            let message = transport.recv()?;
+            if let Some(reply) = self.handle(message)? {
+                transport.send(reply)?;
+            }
+        }
+        Ok(self.session_key().unwrap().clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ServerState {
+    Start,
+    SentChallenge {
+        username: String,
+        salt: Bytes,
+        a_pub: BigUint,
+        b_pub: BigUint,
+        key: Bytes,
+    },
+    Done {
+        username: String,
+        key: Bytes,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Server<H: Digest> {
+    srp: Srp<H>,
+    users: HashMap<String, (Bytes, BigUint)>,
+    check_public_key: bool,
+    state: ServerState,
+}
+
+impl<H: Digest> Server<H> {
+    pub fn new(srp: Srp<H>) -> Self {
+        Self {
+            srp,
+            users: HashMap::new(),
+            check_public_key: true,
+            state: ServerState::Start,
+        }
+    }
+
+    /// Skips rejecting A = 0 mod N, which lets anyone log in as any user
+    pub fn insecure(mut self) -> Self {
+        self.check_public_key = false;
+        self
+    }
+
+    /// Stores a random salt and the verifier for `password`. The password itself isn't kept
+    pub fn add_user(&mut self, username: impl Into<String>, password: &str) {
+        let username = username.into();
+        let salt = Bytes::rand(16);
+        let v = self.srp.verifier(&salt, &username, password);
+        self.users.insert(username, (salt, v));
+    }
+
+    /// Handles a message from the client, returning the reply if there is one
+    ///
+    /// Failures are reported to the client with `Message::Failed`, as well as returned
+    pub fn handle(&mut self, message: Message) -> Result<Option<Message>, Error> {
+        let n = &self.srp.group.n;
+        match (&self.state, message) {
+            (ServerState::Start, Message::Hello { username, a_pub }) => {
+                let (salt, v) = self.users.get(&username).ok_or(Error::UnknownUser)?;
+                if self.check_public_key && (&a_pub % n).bits() == 0 {
+                    return Err(Error::InvalidPublicKey);
+                }
+                let b = self.srp.private_key();
+                let b_pub = (self.srp.k() * v + self.srp.group.g.modpow(&b, n)) % n;
+                let u = self.srp.u(&a_pub, &b_pub);
+                let s = (&a_pub * v.modpow(&u, n)).modpow(&b, n);
+                let key = self.srp.session_key(&s);
+                let salt = salt.clone();
+                self.state = ServerState::SentChallenge {
+                    username,
+                    salt: salt.clone(),
+                    a_pub,
+                    b_pub: b_pub.clone(),
+                    key,
+                };
+                Ok(Some(Message::Challenge { salt, b_pub }))
+            }
+            (
+                ServerState::SentChallenge {
+                    username,
+                    salt,
+                    a_pub,
+                    b_pub,
+                    key,
+                },
+                Message::ClientProof { m1 },
+            ) => {
+                if !self.srp.m1(username, salt, a_pub, b_pub, key).ct_eq(&m1) {
+                    return Err(Error::BadProof);
+                }
+                let m2 = self.srp.m2(a_pub, &m1, key);
+                self.state = ServerState::Done {
+                    username: username.clone(),
+                    key: key.clone(),
+                };
+                Ok(Some(Message::ServerProof { m2 }))
+            }
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    /// The authenticated user and shared key, once the client has proven it knows the password
+    pub fn authenticated(&self) -> Option<(&str, &Bytes)> {
+        match &self.state {
+            ServerState::Done { username, key } => Some((username, key)),
+            _ => None,
+        }
+    }
+
+    /// Runs the whole exchange over a transport, returning the user and session key
+    pub fn run(
+        mut self,
+        transport: &mut impl Transport<Message>,
+    ) -> Result<(String, Bytes), Error> {
+        while self.authenticated().is_none() {
+            let message = transport.recv()?;
+            match self.handle(message) {
+                Ok(Some(reply)) => transport.send(reply)?,
+                Ok(None) => (),
+                Err(e) => {
+                    // The client may already be gone, which doesn't change the outcome
+                    let _ = transport.send(Message::Failed);
+                    return Err(e);
+                }
+            }
+        }
+        let (username, key) = self.authenticated().unwrap();
+        Ok((username.to_string(), key.clone()))
+    }
+}
+
+/// Logs in as `username` without the password, against a server that doesn't check A
+///
+/// Sends A = `multiple` * N, so the server computes S = (A * v^u)^b = 0, and the session key is
+/// just H(PAD(0)). Returns that key if the server accepts the forged proof.
+pub fn zero_key_login<H: Digest>(
+    srp: &Srp<H>,
+    username: &str,
+    multiple: u32,
+    transport: &mut impl Transport<Message>,
+) -> Result<Bytes, Error> {
+    let a_pub = &srp.group.n * multiple;
+    transport.send(Message::Hello {
+        username: username.to_string(),
+        a_pub: a_pub.clone(),
+    })?;
+    let (salt, b_pub) = match transport.recv()? {
+        Message::Challenge { salt, b_pub } => (salt, b_pub),
+        Message::Failed => return Err(Error::Rejected),
+        _ => return Err(Error::UnexpectedMessage),
+    };
+    let key = srp.session_key(&BigUint::from(0u32));
+    let m1 = srp.m1(username, &salt, &a_pub, &b_pub, &key);
+    transport.send(Message::ClientProof { m1: m1.clone() })?;
+    match transport.recv()? {
+        Message::ServerProof { m2 } if m2.ct_eq(&srp.m2(&a_pub, &m1, &key)) => Ok(key),
+        Message::ServerProof { .. } => Err(Error::BadProof),
+        Message::Failed => Err(Error::Rejected),
+        _ => Err(Error::UnexpectedMessage),
+    }
+}
+
+/// Simplified SRP, where B = g^b doesn't involve the verifier and the server picks u
+pub mod simplified {
+    use super::*;
+
+    /// The client's proof, HMAC(K, salt), with S = B^(a + u*x) and K = H(PAD(S))
+    pub fn client_proof<H: Digest>(
+        srp: &Srp<H>,
+        username: &str,
+        password: &str,
+        a: &BigUint,
+        salt: &Bytes,
+        b_pub: &BigUint,
+        u: &BigUint,
+    ) -> Bytes {
+        let x = srp.x(salt, username, password);
+        let s = b_pub.modpow(&(a + u * x), &srp.group.n);
+        Hmac::<H>::mac(&srp.session_key(&s), salt)
+    }
+
+    /// Offline attack from the server's side of a simplified SRP exchange
+    ///
+    /// Since B doesn't depend on the password, anyone can pose as the server. Given the b and u
+    /// they used, each candidate password can be checked by computing S = (A * v^u)^b and
+    /// comparing the resulting proof.
+    #[allow(clippy::too_many_arguments)]
+    pub fn crack<H: Digest + Sync>(
+        srp: &Srp<H>,
+        dictionary: &Dictionary,
+        username: &str,
+        a_pub: &BigUint,
+        salt: &Bytes,
+        b: &BigUint,
+        u: &BigUint,
+        proof: &Bytes,
+    ) -> io::Result<Cracked> {
+        let n = &srp.group.n;
+        dictionary.crack(|candidate| {
+            let v = srp.verifier(salt, username, candidate);
+            let s = (a_pub * v.modpow(u, n)).modpow(b, n);
+            Hmac::<H>::mac(&srp.session_key(&s), salt) == *proof
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comms::comm_channel;
+    use crate::comms::transport::{Channel, StreamTransport};
+    use crate::hash::{sha1::Sha1, sha2::Sha256};
+    use std::sync::mpsc::channel;
+
+    /// RFC 5054, appendix B
+    #[test]
+    fn rfc_5054() {
+        let srp = Srp::<Sha1>::new(RFC5054_1024.clone());
+        let hex = |s: &str| BigUint::parse_bytes(s.as_bytes(), 16).unwrap();
+        let salt = Bytes::read_hex("BEB25379D1A8581EB5A727673A2441EE");
+        assert_eq!(srp.k(), hex("7556AA045AEF2CDD07ABAF0F665C3E818913186F"));
+        assert_eq!(
+            srp.x(&salt, "alice", "password123"),
+            hex("94B7555AABE9127CC58CCF4993DB6CF84D16C124")
+        );
+        let v = srp.verifier(&salt, "alice", "password123");
+        let a = hex("60975527035CF2AD1989806F0407210BC81EDC04E2762A56AFD529DDDA2D4393");
+        let b = hex("E487CB59D31AC550471E81F00F6928E01DDA08E974A004F49E61F5D105284D20");
+        let n = &srp.group.n;
+        let a_pub = srp.group.g.modpow(&a, n);
+        let b_pub = (srp.k() * &v + srp.group.g.modpow(&b, n)) % n;
+        let u = srp.u(&a_pub, &b_pub);
+        assert_eq!(u, hex("CE38B9593487DA98554ED47D70A7AE5F462EF019"));
+        let s = (&a_pub * v.modpow(&u, n)).modpow(&b, n);
+        let premaster = srp.group.pad(&s).to_hex();
+        assert!(premaster.starts_with("B0DC82BABCF30674AE"));
+        assert!(premaster.ends_with("CA686E5A"));
+    }
+
+    #[test]
+    fn exchange() {
+        let srp = Srp::<Sha256>::new(RFC5054_1024.clone());
+        let mut server = Server::new(srp.clone());
+        server.add_user("alice", "password123");
+        let client = Client::new(srp.clone(), "alice", "password123");
+        let (results, collected) = channel();
+        let server_results = results.clone();
+        comm_channel(
+            move |tx, rx| {
+                let key = client
+                    .clone()
+                    .run(&mut Channel::new(tx, rx))
+                    .map(|key| key.to_hex());
+                results.send(key).unwrap()
+            },
+            move |tx, rx| {
+                let key = server
+                    .clone()
+                    .run(&mut Channel::new(tx, rx))
+                    .map(|(_, key)| key.to_hex());
+                server_results.send(key).unwrap()
+            },
+        );
+        let keys: Vec<Result<String, Error>> = collected.iter().collect();
+        assert_eq!(keys.len(), 2);
+        assert!(keys[0].is_ok());
+        assert_eq!(keys[0], keys[1]);
+    }
+
+    #[test]
+    fn exchange_over_tcp() {
+        let srp = Srp::<Sha256>::new(RFC5054_1024.clone());
+        let mut server = Server::new(srp.clone());
+        server.add_user("alice", "password123");
+        let (mut a, mut b) = StreamTransport::<std::net::TcpStream, _>::loopback().unwrap();
+        let handle = std::thread::spawn(move || server.run(&mut b));
+        let key = Client::new(srp.clone(), "alice", "password123")
+            .run(&mut a)
+            .unwrap();
+        assert_eq!(handle.join().unwrap().unwrap(), ("alice".to_string(), key));
+
+        let mut server = Server::new(srp.clone());
+        server.add_user("alice", "password123");
+        let (mut a, mut b) = StreamTransport::<std::net::TcpStream, _>::loopback().unwrap();
+        let handle = std::thread::spawn(move || server.run(&mut b));
+        let result = Client::new(srp, "mallory", "password123").run(&mut a);
+        assert_eq!(result, Err(Error::Rejected));
+        assert_eq!(handle.join().unwrap(), Err(Error::UnknownUser));
+    }
+
+    #[test]
+    fn message_wire() {
+        for message in [
+            Message::Hello {
+                username: "alice".to_string(),
+                a_pub: BigUint::from(12345u32),
+            },
+            Message::Challenge {
+                salt: Bytes::rand(16),
+                b_pub: RFC5054_1024.n.clone(),
+            },
+            Message::ClientProof {
+                m1: Bytes::rand(32),
+            },
+            Message::ServerProof {
+                m2: Bytes::rand(32),
+            },
+            Message::Failed,
+        ] {
+            assert_eq!(Message::from_wire(&message.to_wire()), Ok(message));
+        }
+        assert!(Message::from_wire(&[5]).is_err());
+        assert_eq!(
+            Message::from_wire(&[3, 0, 0, 0, 9]),
+            Err(comms::Error::Malformed(
+                "needed 9 bytes, but only 0 left".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn wrong_password() {
+        let srp = Srp::<Sha256>::new(RFC5054_1024.clone());
+        let mut server = Server::new(srp.clone());
+        server.add_user("alice", "password123");
+        let mut client = Client::new(srp, "alice", "password124");
+        let challenge = server.handle(client.start()).unwrap().unwrap();
+        let proof = client.handle(challenge).unwrap().unwrap();
+        assert_eq!(server.handle(proof), Err(Error::BadProof));
+        assert!(server.authenticated().is_none());
+    }
+
+    #[test]
+    fn zero_key() {
+        let srp = Srp::<Sha256>::new(RFC5054_1024.clone());
+        let mut server = Server::new(srp.clone());
+        server.add_user("alice", "password123");
+        for (server, accepted) in [(server.clone().insecure(), true), (server, false)] {
+            for multiple in 0..3 {
+                let (results, collected) = channel();
+                let attacker = srp.clone();
+                let server = server.clone();
+                comm_channel(
+                    move |tx, rx| {
+                        let result =
+                            zero_key_login(&attacker, "alice", multiple, &mut Channel::new(tx, rx));
+                        results.send(result.is_ok()).unwrap()
+                    },
+                    move |tx, rx| {
+                        let _ = server.clone().run(&mut Channel::new(tx, rx));
+                    },
+                );
+                assert_eq!(collected.recv().unwrap(), accepted);
+            }
+        }
+    }
+
+    #[test]
+    fn simplified_crack() {
+        let srp = Srp::<Sha256>::new(RFC5054_1024.clone());
+        let n = &srp.group.n;
+        let a = srp.private_key();
+        let a_pub = srp.group.g.modpow(&a, n);
+        // The attacker posing as the server picks b = 1 and u = 1, so S = A * v
+        let (b, u, salt) = (BigUint::from(1u32), BigUint::from(1u32), Bytes::rand(16));
+        let b_pub = srp.group.g.modpow(&b, n);
+        let proof = simplified::client_proof(&srp, "alice", "Monkey7", &a, &salt, &b_pub, &u);
+        let dictionary = Dictionary::from_words(vec!["donkey", "monkey", "turkey"])
+            .rule(crate::dictionary::Rule::Capitalize)
+            .rule(crate::dictionary::Rule::Digits(1));
+        let cracked =
+            simplified::crack(&srp, &dictionary, "alice", &a_pub, &salt, &b, &u, &proof).unwrap();
+        assert_eq!(cracked.password.as_deref(), Some("Monkey7"));
+    }
+}
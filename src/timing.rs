@@ -0,0 +1,351 @@
+//
+// timing.rs
+// Copyright (C) 2022 matthew <matthew@WINDOWS-05HIC4F>
+// Distributed under terms of the MIT license.
+//
+
+//! Statistical timing attack against early exit comparisons
+//!
+//! The signature is recovered one byte at a time. For each position, every candidate byte is timed
+//! many times, and the samples are summarised with a statistic that ignores outliers (the median,
+//! or a trimmed mean). The slowest candidate is only accepted if it stands out from the rest - if
+//! no candidate does, the previous byte was probably wrong, so the attack backtracks.
+
+use rand::random;
+use std::time::Instant;
+
+use crate::data::Bytes;
+
+/// How the samples for each candidate are summarised
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statistic {
+    Median,
+    /// Mean after discarding this fraction of the samples from each end
+    TrimmedMean(f64),
+}
+
+impl Statistic {
+    fn apply(&self, samples: &[f64]) -> f64 {
+        match self {
+            Self::Median => median(samples),
+            Self::TrimmedMean(trim) => mean(&trimmed(samples, *trim)),
+        }
+    }
+
+    /// Fraction trimmed before running the significance test
+    fn trim(&self) -> f64 {
+        match self {
+            Self::Median => 0.25,
+            Self::TrimmedMean(trim) => *trim,
+        }
+    }
+}
+
+/// The result of attacking a single byte
+#[derive(Debug, Clone, PartialEq)]
+pub struct ByteGuess {
+    pub byte: u8,
+    /// How many standard deviations the best candidate is above the others
+    pub separation: f64,
+    /// Welch's t statistic between the best and second best candidate
+    pub confidence: f64,
+    /// The verifier accepted the guess (padded with zeros) outright
+    pub accepted: bool,
+}
+
+/// Timing attack against any verifier that exits on the first wrong byte
+#[derive(Debug, Clone)]
+pub struct TimingAttack {
+    samples: usize,
+    statistic: Statistic,
+    threshold: f64,
+    retries: usize,
+    max_backtracks: usize,
+}
+
+impl Default for TimingAttack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimingAttack {
+    pub fn new() -> Self {
+        Self {
+            samples: 5,
+            statistic: Statistic::Median,
+            threshold: 4.0,
+            retries: 3,
+            max_backtracks: 8,
+        }
+    }
+
+    /// Number of times each candidate is timed per round
+    pub fn samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    pub fn statistic(mut self, statistic: Statistic) -> Self {
+        self.statistic = statistic;
+        self
+    }
+
+    /// Minimum separation and confidence to accept a byte without resampling
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Number of extra rounds of samples to take when a byte doesn't meet the threshold
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Total number of times the attack can back up a byte before giving up
+    pub fn max_backtracks(mut self, max_backtracks: usize) -> Self {
+        self.max_backtracks = max_backtracks;
+        self
+    }
+
+    /// Recovers a `len` byte signature that `verify` accepts
+    ///
+    /// Returns `None` if the attack ran out of backtracks
+    pub fn recover(&self, verify: impl Fn(&Bytes) -> bool, len: usize) -> Option<Bytes> {
+        let mut known = Bytes::new();
+        let mut backtracks = 0;
+        while known.len() < len {
+            if known.len() + 1 == len {
+                // The last byte doesn't change the timing, but the verifier will accept it
+                for candidate in 0..=255u8 {
+                    let signature = known.clone() + candidate;
+                    if verify(&signature) {
+                        return Some(signature);
+                    }
+                }
+            } else {
+                let guess = self.recover_byte(&verify, &known, len);
+                if guess.accepted {
+                    return Some(pad(&known, guess.byte, len));
+                }
+                if guess.separation >= self.threshold || known.is_empty() {
+                    known += guess.byte;
+                    continue;
+                }
+            }
+            // Nothing stood out, so the previous byte is probably wrong
+            if backtracks >= self.max_backtracks || known.is_empty() {
+                return None;
+            }
+            backtracks += 1;
+            known = known.truncate(known.len() - 1);
+        }
+        Some(known)
+    }
+
+    /// Finds the byte after `known`, for a signature of `len` bytes
+    ///
+    /// Every candidate is timed once per round, so noise (e.g. another process waking up) is
+    /// spread across all candidates, rather than landing on just one.
+    pub fn recover_byte(
+        &self,
+        verify: impl Fn(&Bytes) -> bool,
+        known: &Bytes,
+        len: usize,
+    ) -> ByteGuess {
+        let mut times = vec![Vec::new(); 256];
+        let mut attempt = 0;
+        loop {
+            for _ in 0..self.samples {
+                for (candidate, times) in times.iter_mut().enumerate() {
+                    let signature = pad(known, candidate as u8, len);
+                    let start = Instant::now();
+                    let valid = verify(&signature);
+                    let elapsed = start.elapsed().as_nanos() as f64;
+                    if valid {
+                        return ByteGuess {
+                            byte: candidate as u8,
+                            separation: f64::INFINITY,
+                            confidence: f64::INFINITY,
+                            accepted: true,
+                        };
+                    }
+                    times.push(elapsed);
+                }
+            }
+            let guess = self.evaluate(&times);
+            if attempt >= self.retries
+                || (guess.separation >= self.threshold && guess.confidence >= self.threshold)
+            {
+                return guess;
+            }
+            attempt += 1;
+        }
+    }
+
+    fn evaluate(&self, times: &[Vec<f64>]) -> ByteGuess {
+        let scores: Vec<f64> = times.iter().map(|t| self.statistic.apply(t)).collect();
+        let mut order: Vec<usize> = (0..scores.len()).collect();
+        order.sort_by(|a, b| scores[*b].partial_cmp(&scores[*a]).unwrap());
+        let (best, second) = (order[0], order[1]);
+
+        let others: Vec<f64> = order[1..].iter().map(|&i| scores[i]).collect();
+        let spread = std_dev(&others);
+        let separation = if spread > 0.0 {
+            (scores[best] - mean(&others)) / spread
+        } else {
+            f64::INFINITY
+        };
+
+        let trim = self.statistic.trim();
+        let confidence = welch_t(&trimmed(&times[best], trim), &trimmed(&times[second], trim));
+        ByteGuess {
+            byte: best as u8,
+            separation,
+            confidence,
+            accepted: false,
+        }
+    }
+}
+
+/// `known`, then `candidate`, then zeros up to `len` bytes
+fn pad(known: &Bytes, candidate: u8, len: usize) -> Bytes {
+    let signature = known.clone() + candidate;
+    let padding = Bytes::zero(len - signature.len());
+    signature + padding
+}
+
+fn sorted(samples: &[f64]) -> Vec<f64> {
+    let mut ret = samples.to_vec();
+    ret.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    ret
+}
+
+pub fn median(samples: &[f64]) -> f64 {
+    let sorted = sorted(samples);
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Sorts the samples, then discards `trim` of them from each end
+pub fn trimmed(samples: &[f64], trim: f64) -> Vec<f64> {
+    let sorted = sorted(samples);
+    let cut = (sorted.len() as f64 * trim) as usize;
+    if cut * 2 >= sorted.len() {
+        vec![median(samples)]
+    } else {
+        sorted[cut..sorted.len() - cut].to_vec()
+    }
+}
+
+pub fn mean(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Sample variance
+pub fn variance(samples: &[f64]) -> f64 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(samples);
+    samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (samples.len() - 1) as f64
+}
+
+pub fn std_dev(samples: &[f64]) -> f64 {
+    variance(samples).sqrt()
+}
+
+/// Welch's t statistic for whether `a` has a larger mean than `b`
+pub fn welch_t(a: &[f64], b: &[f64]) -> f64 {
+    let diff = mean(a) - mean(b);
+    let err = (variance(a) / a.len() as f64 + variance(b) / b.len() as f64).sqrt();
+    if err > 0.0 {
+        diff / err
+    } else if diff > 0.0 {
+        f64::INFINITY
+    } else {
+        0.0
+    }
+}
+
+/// `leakage` results further than this from zero indicate a timing difference
+pub const LEAKAGE_THRESHOLD: f64 = 4.5;
+
+/// Checks whether `a` and `b` take measurably different amounts of time
+///
+/// Runs each `samples` times, in a random order so drift affects both equally, then returns
+/// Welch's t statistic over the trimmed timings. `a` being slower gives a positive result.
+pub fn leakage(mut a: impl FnMut(), mut b: impl FnMut(), samples: usize) -> f64 {
+    let time = |f: &mut dyn FnMut()| {
+        let start = Instant::now();
+        f();
+        start.elapsed().as_nanos() as f64
+    };
+    let mut times_a = Vec::with_capacity(samples);
+    let mut times_b = Vec::with_capacity(samples);
+    for _ in 0..samples {
+        if random() {
+            times_a.push(time(&mut a));
+            times_b.push(time(&mut b));
+        } else {
+            times_b.push(time(&mut b));
+            times_a.push(time(&mut a));
+        }
+    }
+    welch_t(&trimmed(&times_a, 0.1), &trimmed(&times_b, 0.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn spin(time: Duration) {
+        let start = Instant::now();
+        while start.elapsed() < time {}
+    }
+
+    /// Early exit compare, with a delay after each correct byte
+    fn weak_verify(actual: &Bytes, signature: &Bytes) -> bool {
+        for (a, b) in actual.iter().zip(signature.iter()) {
+            if a != b {
+                return false;
+            }
+            spin(Duration::from_micros(50));
+        }
+        actual.len() == signature.len()
+    }
+
+    #[test]
+    fn statistics() {
+        let samples = [5.0, 1.0, 3.0, 100.0, 2.0, 4.0];
+        assert_eq!(median(&samples), 3.5);
+        assert_eq!(trimmed(&samples, 0.2), vec![2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(mean(&trimmed(&samples, 0.2)), 3.5);
+        assert!(welch_t(&[10.0, 11.0, 10.5], &[1.0, 1.5, 2.0]) > 10.0);
+    }
+
+    // The spin timings are swamped by whatever else is running, so this is run by hand with
+    // `cargo test --release -- --ignored timing::tests::recover`
+    #[test]
+    #[ignore = "timing measurement, run by hand in release mode"]
+    fn recover() {
+        let actual = Bytes::rand(4);
+        let verify = |signature: &Bytes| weak_verify(&actual, signature);
+        let recovered = TimingAttack::new()
+            .samples(3)
+            .recover(&verify, actual.len());
+        assert_eq!(recovered, Some(actual.clone()));
+
+        let recovered = TimingAttack::new()
+            .samples(3)
+            .statistic(Statistic::TrimmedMean(0.2))
+            .recover(&verify, actual.len());
+        assert_eq!(recovered, Some(actual));
+    }
+}